@@ -1,19 +1,74 @@
-use std::collections::HashMap;
 use std::env;
 use std::fs;
 
 use searchkit::index::inverted::{InvBuilder, InvertedIndex};
-use searchkit::rank::bm25::BM25;
-// use the same tokenizer your InvBuilder uses, so query terms match
+use searchkit::query::engine::{CollectionStats, QueryEngine};
+use searchkit::query::suggest::suggest_query;
+use searchkit::rank::bm25::{rank_fields, FieldParams, BM25F};
+use searchkit::rank::similarity::{similarity_by_name, SimilarityParams};
 use searchkit::text::tokenize::tokenize;
 
+/// With `--suggest`, corrections are offered below this many results.
+const FEW_RESULTS: usize = 3;
+
+/// Remove `flag <value>` from `args`, returning the value.
+fn take_flag(args: &mut Vec<String>, flag: &str) -> Option<String> {
+    let i = args.iter().position(|a| a == flag)?;
+    let Some(v) = args.get(i + 1).cloned() else { fail(&format!("{flag} needs a value")) };
+    args.drain(i..i + 2);
+    Some(v)
+}
+
+fn take_num(args: &mut Vec<String>, flag: &str) -> Option<f32> {
+    take_flag(args, flag).map(|v| v.parse().unwrap_or_else(|_| fail(&format!("{flag} needs a number"))))
+}
+
+/// Comma-separated numbers, e.g. `--field-weights 3,1`.
+fn take_list(args: &mut Vec<String>, flag: &str) -> Option<Vec<f32>> {
+    take_flag(args, flag).map(|v| {
+        v.split(',').map(|x| x.trim().parse().unwrap_or_else(|_| fail(&format!("{flag}: bad number {x:?}")))).collect()
+    })
+}
+
+fn fail(msg: &str) -> ! {
+    eprintln!("{msg}");
+    std::process::exit(1);
+}
+
 fn main() {
     // 1) parse CLI
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    let suggest = args.iter().any(|a| a == "--suggest");
+    args.retain(|a| a != "--suggest");
+    let similarity = take_flag(&mut args, "--similarity").unwrap_or_else(|| "bm25".into());
+    let params = SimilarityParams {
+        k1: take_num(&mut args, "--k1"),
+        b: take_num(&mut args, "--b"),
+        delta: take_num(&mut args, "--delta"),
+        mu: take_num(&mut args, "--mu"),
+        lambda: take_num(&mut args, "--lambda"),
+        smart: take_flag(&mut args, "--smart"),
+        c: take_num(&mut args, "--c"),
+    };
+    let field_weights = take_list(&mut args, "--field-weights");
+    let field_b = take_list(&mut args, "--field-b");
     if args.len() < 4 {
-        eprintln!("Usage: {} <corpus.txt> <query> <topk>", args[0]);
+        eprintln!(
+            "Usage: {} <corpus.txt> <query> <topk> [--suggest] [--similarity bm25|bm25+|bm25l|bm25f|lm-dirichlet|lm-jm|vsm|pl2|inl2|dph]\n\
+             \x20      [--k1 N] [--b N] [--delta N] [--mu N] [--lambda N] [--smart lnc.ltc] [--c N]\n\
+             \x20      [--field-weights 3,1 [--field-b 0.75,0.75]]  (bm25f: tab-separated fields per line)",
+            args[0]
+        );
         std::process::exit(1);
     }
+    // bm25f ranks the tab-separated fields of each line, everything else the whole line
+    let fielded = similarity.eq_ignore_ascii_case("bm25f");
+    match (fielded, field_weights.is_some()) {
+        (true, false) => fail("--similarity bm25f needs --field-weights, one per tab-separated field"),
+        (false, true) => fail("--field-weights needs --similarity bm25f"),
+        _ => {}
+    }
+    let sim = (!fielded).then(|| similarity_by_name(&similarity, &params).unwrap_or_else(|e| fail(&e.to_string())));
     let corpus_path = &args[1];
     let query_raw = &args[2];
     let topk: usize = args[3].parse().unwrap_or(10);
@@ -21,7 +76,6 @@ fn main() {
     // 2) load corpus (one document per line)
     let corpus = fs::read_to_string(corpus_path).expect("Failed to read corpus file");
     let docs: Vec<&str> = corpus.lines().collect();
-    let n_docs = docs.len() as f32;
 
     // 3) build inverted index via your InvBuilder (which uses your tokenizer)
     let mut builder = InvBuilder::default();
@@ -30,65 +84,42 @@ fn main() {
     }
     let inv: InvertedIndex = builder.finalize();
 
-    // 4) compute per-doc lengths (sum of term positions across all terms)
-    //    and avgdl for BM25
-    let mut lens: HashMap<u32, u32> = HashMap::new();
-    for term in inv.dict.keys() {
-        for (docid, positions) in inv.postings(term) {
-            *lens.entry(docid).or_insert(0) += positions.len() as u32;
-        }
-    }
-
-    // ensure every doc has a length (even if it had zero tokens)
-    for d in 0..docs.len() {
-        lens.entry(d as u32).or_insert(1);
-    }
-
-    let total_len: u32 = lens.values().copied().sum();
-    let avgdl: f32 = if !lens.is_empty() {
-        total_len as f32 / lens.len() as f32
-    } else {
-        1.0
-    };
-    println!("📊 avgdl = {}, total docs = {}", avgdl, lens.len());
+    // 4) collection stats (doc lengths, avgdl) for ranking
+    let stats = CollectionStats::from_index(&inv, Some(docs.len() as u32));
+    println!("📊 avgdl = {}, total docs = {}, similarity = {}", stats.avgdl, stats.n_docs, similarity);
+    let mut engine = QueryEngine::with_stats(inv, stats);
+    if let Some(sim) = sim { engine = engine.with_similarity(sim).unwrap_or_else(|e| fail(&e.to_string())); }
 
-    // 5) tokenize the query using the same tokenizer as the index
-    let mut terms: Vec<String> = Vec::new();
-    for (tok, _pos) in tokenize(query_raw) {
-        terms.push(tok);
-    }
-    if terms.is_empty() {
+    // 5) free-text query: tokens are ORed and ranking sorts them out
+    let Some(query) = QueryEngine::parse_free_text(query_raw).unwrap_or_else(|e| fail(&e.to_string())) else {
         eprintln!("Query produced no tokens after tokenization.");
         std::process::exit(1);
-    }
-
-    // 6) score with BM25
-    let bm25 = BM25::new(1.5, 0.75, avgdl);
-    let mut scores: HashMap<u32, f32> = HashMap::new();
+    };
+    let terms: Vec<String> = tokenize(query_raw).into_iter().map(|(t, _)| t).collect();
 
-    for term in &terms {
-        let postings = inv.postings(term);
-        if postings.is_empty() {
-            continue;
+    // 6) score, rank and print
+    let ranked = match field_weights {
+        Some(weights) => {
+            let b = params.b.unwrap_or(0.75);
+            let bs = field_b.unwrap_or_else(|| vec![b; weights.len()]);
+            if bs.len() != weights.len() { fail("--field-b needs one value per field weight"); }
+            let fields: Vec<FieldParams> = weights.iter().zip(&bs).map(|(&weight, &b)| FieldParams { weight, b }).collect();
+            let bm25f = BM25F::new(params.k1.unwrap_or(1.2), fields);
+            rank_fields(&bm25f, &docs, &terms, topk)
         }
-        let df = postings.len() as f32;
+        None => engine.search(&query, topk).expect("query failed"),
+    };
 
-        for (docid, positions) in postings {
-            let tf = positions.len() as f32;
-            let dl = *lens.get(&docid).unwrap_or(&1) as f32;
-            let s = bm25.score(tf, df, n_docs, dl);
-            *scores.entry(docid).or_insert(0.0) += s;
-        }
+    println!("\n🔎 Top {} results for query: \"{}\"", topk, query_raw);
+    for (i, hit) in ranked.iter().enumerate() {
+        let line = docs.get(hit.doc as usize).unwrap_or(&"<out of range>");
+        println!(" {}. doc={}  score={:.6}\n    📄 {}", i + 1, hit.doc, hit.score, line);
     }
 
-    // 7) rank and print
-    let mut ranked: Vec<(u32, f32)> = scores.into_iter().collect();
-    ranked.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
-
-    println!("\n🔎 Top {} results for query: \"{}\"", topk, query_raw);
-    for (i, (doc_id, score)) in ranked.iter().take(topk).enumerate() {
-        let line = docs.get(*doc_id as usize).unwrap_or(&"<out of range>");
-        println!(" {}. doc={}  score={:.6}\n    📄 {}", i + 1, doc_id, score, line);
+    // 7) "did you mean" from the index vocabulary when results are thin
+    if suggest && ranked.len() < FEW_RESULTS.min(topk.max(1)) {
+        for fix in suggest_query(engine.index(), &terms, 2, 3) {
+            println!("💡 Did you mean: \"{}\" ({} edits)", fix.text(), fix.distance);
+        }
     }
 }
-
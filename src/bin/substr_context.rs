@@ -1,4 +1,144 @@
-fn main() {
-    println!("substr-context: not implemented yet");
+use std::env;
+
+use searchkit::index::fmindex::FMIndex;
+use searchkit::query::substring::substring_count;
+
+/// Default bytes of context on each side of a match.
+const DEFAULT_WIDTH: usize = 30;
+
+#[derive(Clone, Copy, PartialEq)]
+enum SortBy {
+    Offset,
+    Left,
+    Right,
+}
+
+/// A located match: its offset and SA row, label, and the bounds its
+/// context is clipped to.
+struct Hit {
+    off: usize,
+    row: u64,
+    label: String,
+    lo: usize,
+    hi: usize,
+}
+
+/// One concordance line: a match with the text around it.
+struct Line {
+    label: String,
+    left: Vec<u8>,
+    hit: Vec<u8>,
+    right: Vec<u8>,
+}
+
+/// Printable single-line rendering of context bytes.
+fn show(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).chars().map(|c| if c.is_control() { ' ' } else { c }).collect()
 }
 
+fn usage(prog: &str) -> ! {
+    eprintln!("Usage: {prog} <index.fm> <pattern> [--width N] [--sort offset|left|right] [--max N]");
+    std::process::exit(1);
+}
+
+fn main() {
+    // 1) parse CLI
+    let args: Vec<String> = env::args().collect();
+    let mut pos = Vec::new();
+    let (mut width, mut sort, mut max) = (DEFAULT_WIDTH, SortBy::Offset, usize::MAX);
+    let mut it = args.iter().skip(1);
+    while let Some(a) = it.next() {
+        let mut val = || it.next().cloned().unwrap_or_else(|| usage(&args[0]));
+        match a.as_str() {
+            "--width" => width = val().parse().unwrap_or_else(|_| usage(&args[0])),
+            "--max" => max = val().parse().unwrap_or_else(|_| usage(&args[0])),
+            "--sort" => {
+                sort = match val().as_str() {
+                    "offset" => SortBy::Offset,
+                    "left" => SortBy::Left,
+                    "right" => SortBy::Right,
+                    _ => usage(&args[0]),
+                }
+            }
+            _ => pos.push(a.clone()),
+        }
+    }
+    if pos.len() != 2 {
+        usage(&args[0]);
+    }
+    let (fm_path, pat) = (&pos[0], pos[1].as_bytes());
+
+    // 2) load the index (with its doc table, if it has one)
+    let fm = FMIndex::load(fm_path).expect("Failed to load FM-index");
+    let docs = fm.docs();
+
+    // 3) find the pattern; each SA row gives both the offset and the walk
+    //    start. In offset order only --max matches are shown, so stop there
+    let Some(range) = fm.backward_search(pat) else {
+        println!("no matches");
+        return;
+    };
+    let total = docs.map_or((range.r - range.l) as usize, |docs| substring_count(&fm, docs, pat));
+    let cap = if sort == SortBy::Offset { max } else { usize::MAX };
+    let mut hits = Vec::new();
+    for row in range.l..range.r {
+        if hits.len() >= cap {
+            break;
+        }
+        let off = fm.locate(row);
+        let (label, lo, hi) = match docs {
+            Some(docs) => {
+                let Some((doc, in_doc)) = docs.resolve(off, pat.len()) else { continue };
+                let (start, len) = docs.span(doc).unwrap();
+                (format!("{}:{in_doc}", docs.label(doc)), start, start + len)
+            }
+            None => (off.to_string(), 0, fm.text_len()),
+        };
+        hits.push(Hit { off, row, label, lo, hi });
+    }
+    hits.sort_unstable_by_key(|h| h.off);
+
+    // 4) context straight from the index: LF steps to the left, psi steps to
+    //    the right, clipped to the document (or to the whole text)
+    let before = |h: &Hit| fm.bytes_before(h.row, width.min(h.off - h.lo));
+    let after = |h: &Hit| fm.bytes_after(h.row, pat.len() + width.min(h.hi.saturating_sub(h.off + pat.len())));
+
+    // 5) concordance order: left context is compared from the match outwards.
+    //    Only the sort key is extracted for every match, the rest for the
+    //    --max shown
+    let mut keyed: Vec<(Vec<u8>, Hit)> = match sort {
+        SortBy::Offset => hits.into_iter().map(|h| (Vec::new(), h)).collect(),
+        SortBy::Left => hits.into_iter().map(|h| (before(&h), h)).collect(),
+        SortBy::Right => hits.into_iter().map(|h| (after(&h), h)).collect(),
+    };
+    match sort {
+        SortBy::Offset => {}
+        SortBy::Left => keyed.sort_by(|a, b| a.0.iter().rev().cmp(b.0.iter().rev())),
+        SortBy::Right => keyed.sort_by(|a, b| a.0.cmp(&b.0)),
+    }
+    keyed.truncate(max);
+    let lines: Vec<Line> = keyed
+        .into_iter()
+        .map(|(key, h)| {
+            let (left, mut hit) = match sort {
+                SortBy::Left => (key, after(&h)),
+                SortBy::Right => (before(&h), key),
+                SortBy::Offset => (before(&h), after(&h)),
+            };
+            let right = hit.split_off(pat.len());
+            Line { label: h.label, left, hit, right }
+        })
+        .collect();
+
+    println!("matches = {total}:");
+    let label_w = lines.iter().map(|l| l.label.len()).max().unwrap_or(0);
+    for l in &lines {
+        println!(
+            "{:>label_w$}  {:>width$} [{}] {}",
+            l.label,
+            show(&l.left),
+            show(&l.hit),
+            show(&l.right),
+        );
+    }
+}
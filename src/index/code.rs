@@ -0,0 +1,177 @@
+use crate::index::doctable::CollectionWriter;
+use crate::util::varint::{decode_varint, encode_varint};
+use anyhow::*;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Files whose first bytes contain NUL are treated as binary and skipped.
+const BINARY_SNIFF: usize = 8192;
+
+/// Source files under `root`, sorted, honouring `.gitignore`, `.ignore` and
+/// git excludes (also outside a git checkout) and skipping hidden entries.
+pub fn walk_source_files(root: &Path) -> Result<Vec<PathBuf>> {
+    let mut out = Vec::new();
+    for entry in ignore::WalkBuilder::new(root).require_git(false).build() {
+        let entry = entry?;
+        if entry.file_type().is_some_and(|t| t.is_file()) {
+            out.push(entry.into_path());
+        }
+    }
+    out.sort();
+    Ok(out)
+}
+
+/// Line starts of every document of a code collection, as offsets within
+/// the document, so FM-index hits map to `line:col`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LineTable {
+    first: Vec<usize>, // per doc, index of its first line in `starts`; one extra at the end
+    starts: Vec<u64>,
+}
+
+/// One hit position: 1-based line and byte column, plus the line's byte
+/// range within the document (without its newline, except that the last
+/// line runs to the end of the document).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinePos {
+    pub line: usize,
+    pub col: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl LineTable {
+    /// Record the next document's lines.
+    pub fn push_doc(&mut self, bytes: &[u8]) {
+        if self.first.is_empty() { self.first.push(0); }
+        self.starts.push(0);
+        for (i, &b) in bytes.iter().enumerate() {
+            if b == b'\n' && i + 1 < bytes.len() { self.starts.push(i as u64 + 1); }
+        }
+        self.first.push(self.starts.len());
+    }
+
+    pub fn n_docs(&self) -> usize { self.first.len().saturating_sub(1) }
+
+    /// Line and column of byte `off` of doc `doc`, whose length is `doc_len`.
+    pub fn locate(&self, doc: u32, off: usize, doc_len: usize) -> Option<LinePos> {
+        let d = doc as usize;
+        let lines = self.starts.get(*self.first.get(d)?..*self.first.get(d + 1)?)?;
+        let idx = lines.partition_point(|&s| s <= off as u64).checked_sub(1)?;
+        let start = lines[idx] as usize;
+        let end = lines.get(idx + 1).map_or(doc_len, |&next| next as usize - 1);
+        Some(LinePos { line: idx + 1, col: off - start + 1, start, end })
+    }
+
+    /// Write with: magic "FML1", u64 n_docs, u64 len + varint line counts,
+    /// u64 len + varint deltas of line starts (restarting at 0 per doc).
+    /// Code indexes keep it in their doc table (see `DocTable::with_lines`).
+    pub fn write_to(&self, f: &mut impl Write) -> Result<()> {
+        f.write_all(b"FML1")?;
+        f.write_all(&(self.n_docs() as u64).to_le_bytes())?;
+        let mut counts = Vec::new();
+        let mut deltas = Vec::new();
+        for w in self.first.windows(2) {
+            encode_varint((w[1] - w[0]) as u64, &mut counts);
+            let mut prev = 0u64;
+            for &s in &self.starts[w[0]..w[1]] {
+                encode_varint(s - prev, &mut deltas);
+                prev = s;
+            }
+        }
+        for section in [counts, deltas] {
+            f.write_all(&(section.len() as u64).to_le_bytes())?;
+            f.write_all(&section)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from(f: &mut impl Read) -> Result<Self> {
+        let mut magic = [0u8; 4];
+        f.read_exact(&mut magic)?;
+        ensure!(&magic == b"FML1", "bad line table file (magic)");
+        let mut b8 = [0u8; 8];
+        f.read_exact(&mut b8)?;
+        let n = u64::from_le_bytes(b8) as usize;
+        let mut section = || -> Result<Vec<u8>> {
+            f.read_exact(&mut b8)?;
+            let mut buf = vec![0u8; u64::from_le_bytes(b8) as usize];
+            f.read_exact(&mut buf)?;
+            Ok(buf)
+        };
+        let (counts, deltas) = (section()?, section()?);
+
+        let mut t = LineTable { first: vec![0], starts: Vec::new() };
+        let (mut i, mut j) = (0usize, 0usize);
+        while i < counts.len() {
+            let (count, next) = decode_varint(&counts, i)?;
+            i = next;
+            let mut acc = 0u64;
+            for _ in 0..count {
+                let (d, next) = decode_varint(&deltas, j)?;
+                j = next;
+                acc += d;
+                t.starts.push(acc);
+            }
+            t.first.push(t.starts.len());
+        }
+        ensure!(t.n_docs() == n, "line table length mismatch");
+        Ok(t)
+    }
+}
+
+/// Call `f(name, bytes)` for every non-binary source file under `root`, with
+/// names relative to `root`.
+pub fn for_each_source(root: &Path, mut f: impl FnMut(&str, &[u8]) -> Result<()>) -> Result<()> {
+    for path in walk_source_files(root)? {
+        let bytes = std::fs::read(&path).with_context(|| format!("reading {}", path.display()))?;
+        if bytes[..bytes.len().min(BINARY_SNIFF)].contains(&0) { continue; }
+        f(&path.strip_prefix(root).unwrap_or(&path).to_string_lossy(), &bytes)?;
+    }
+    Ok(())
+}
+
+/// Read every source file under `root` into a collection (doc names are
+/// paths relative to `root`) and record its lines.
+pub fn collect_sources<W: Write>(root: &Path, coll: &mut CollectionWriter<W>) -> Result<LineTable> {
+    let mut lines = LineTable::default();
+    for_each_source(root, |name, bytes| {
+        coll.add(name, bytes)?;
+        lines.push_doc(bytes);
+        Ok(())
+    })?;
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn walk_respects_gitignore_and_lines_map() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::create_dir_all(root.join("target")).unwrap();
+        std::fs::write(root.join(".gitignore"), "target/\n*.log\n").unwrap();
+        std::fs::write(root.join("src/main.rs"), "fn main() {\n    run();\n}\n").unwrap();
+        std::fs::write(root.join("app.log"), "noise").unwrap();
+        std::fs::write(root.join("target/out.rs"), "fn built() {}").unwrap();
+        std::fs::write(root.join("blob.bin"), b"ab\0cd").unwrap();
+
+        let mut coll = CollectionWriter::new(Vec::new());
+        let lines = collect_sources(root, &mut coll).unwrap();
+        let (_, docs) = coll.finish();
+        assert_eq!(docs.len(), 1);
+        assert_eq!(docs.name(0), Some(format!("src{}main.rs", std::path::MAIN_SEPARATOR).as_str()));
+
+        // "run" is on line 2, column 5; the line is bytes 12..22
+        let (_, len) = docs.span(0).unwrap();
+        assert_eq!(lines.locate(0, 16, len), Some(LinePos { line: 2, col: 5, start: 12, end: 22 }));
+        assert_eq!(lines.locate(0, 24, len).map(|p| (p.line, p.end)), Some((3, len)));
+
+        let mut buf = Vec::new();
+        lines.write_to(&mut buf).unwrap();
+        assert_eq!(LineTable::read_from(&mut buf.as_slice()).unwrap(), lines);
+    }
+}
@@ -0,0 +1,235 @@
+use crate::index::code::LineTable;
+use crate::util::varint::{decode_u64s_varint_delta, decode_varint, encode_u64s_varint_delta, encode_varint};
+use anyhow::*;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+/// Byte written between documents by `CollectionWriter`. It belongs to no
+/// document, so matches running over it never resolve.
+pub const DOC_SEPARATOR: u8 = b'\n';
+
+/// Every byte that can lie outside a document: `DOC_SEPARATOR` and the `\r`
+/// that `from_lines` drops from CRLF line ends. A pattern without them
+/// resolves wherever it matches.
+pub const GAP_BYTES: &[u8] = b"\n\r";
+
+/// Where each document lives inside the single FM-index text.
+///
+/// Spans are `(start, len)` in text bytes, sorted by start; doc id = span
+/// index. Separator bytes between docs belong to no document, so a match that
+/// runs over a separator does not resolve. Collections also carry one name
+/// per doc (file path or record id); line-per-doc tables have none. Code
+/// indexes also carry the line starts of every doc.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DocTable {
+    spans: Vec<(usize, usize)>,
+    names: Vec<String>,
+    lines: Option<LineTable>,
+}
+
+impl DocTable {
+    pub fn from_spans(spans: Vec<(usize, usize)>) -> Self {
+        debug_assert!(spans.windows(2).all(|w| w[0].0 + w[0].1 <= w[1].0));
+        Self { spans, names: Vec::new(), lines: None }
+    }
+
+    /// One doc per line, matching `build-inv` (a trailing `\r` is not part
+    /// of the doc, and a final newline does not start an empty doc).
+    pub fn from_lines(text: &[u8]) -> Self {
+        let mut spans = Vec::new();
+        let mut start = 0usize;
+        while start < text.len() {
+            let end = text[start..].iter().position(|&b| b == b'\n').map_or(text.len(), |i| start + i);
+            let len = if end > start && text[end - 1] == b'\r' { end - start - 1 } else { end - start };
+            spans.push((start, len));
+            start = end + 1;
+        }
+        Self::from_spans(spans)
+    }
+
+    pub fn len(&self) -> usize { self.spans.len() }
+    pub fn is_empty(&self) -> bool { self.spans.is_empty() }
+
+    /// Map a match at text offset `off` of `match_len` bytes to
+    /// `(doc_id, offset_in_doc)`.
+    pub fn resolve(&self, off: usize, match_len: usize) -> Option<(u32, usize)> {
+        let i = self.spans.partition_point(|&(s, _)| s <= off).checked_sub(1)?;
+        let (start, len) = self.spans[i];
+        (off + match_len <= start + len).then_some((i as u32, off - start))
+    }
+
+    /// `(start, len)` of document `doc` in the text.
+    pub fn span(&self, doc: u32) -> Option<(usize, usize)> { self.spans.get(doc as usize).copied() }
+
+    /// Stored name of `doc`, if the table has names.
+    pub fn name(&self, doc: u32) -> Option<&str> { self.names.get(doc as usize).map(String::as_str) }
+
+    /// How to print `doc`: its name, else its id.
+    pub fn label(&self, doc: u32) -> String { self.name(doc).map_or_else(|| doc.to_string(), str::to_string) }
+
+    /// Id of the first doc called `name`.
+    pub fn find(&self, name: &str) -> Option<u32> { self.names.iter().position(|n| n == name).map(|i| i as u32) }
+
+    /// Attach the line starts of every doc (a code index).
+    pub fn with_lines(mut self, lines: LineTable) -> Result<Self> {
+        ensure!(lines.n_docs() == self.len(), "line table has {} docs, doc table {}", lines.n_docs(), self.len());
+        self.lines = Some(lines);
+        Ok(self)
+    }
+
+    pub fn lines(&self) -> Option<&LineTable> { self.lines.as_ref() }
+
+    /// Doc ids are u32; refuse tables that would wrap them.
+    pub fn check_ids(&self) -> Result<()> {
+        ensure!(self.spans.len() as u64 <= u32::MAX as u64 + 1, "{} docs exceed the u32 doc id range", self.spans.len());
+        Ok(())
+    }
+
+    /// Conventional location of the table next to an FM-index file (older
+    /// builds; current ones store the table inside the FMX file).
+    pub fn sidecar_path(fm_path: &str) -> String { format!("{fm_path}.docs") }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        let mut f = BufWriter::new(File::create(path)?);
+        self.write_to(&mut f)?;
+        f.flush()?;
+        Ok(())
+    }
+
+    pub fn load(path: &str) -> Result<Self> {
+        Self::read_from(&mut BufReader::new(File::open(path)?))
+    }
+
+    /// Write with: magic "FMD3", u64 n_docs, u64 len + varint-delta starts,
+    /// u64 len + varint lens, u64 len + names as (varint len, UTF-8 bytes)
+    /// (no names: empty section), u64 len + a `LineTable` (none: empty
+    /// section). FMD2 lacks the line table and FMD1 the names too.
+    pub fn write_to(&self, w: &mut impl Write) -> Result<()> {
+        self.check_ids()?;
+        w.write_all(b"FMD3")?;
+        w.write_all(&(self.spans.len() as u64).to_le_bytes())?;
+        let starts = encode_u64s_varint_delta(self.spans.iter().map(|&(s, _)| s as u64).collect());
+        let mut lens = Vec::new();
+        for &(_, l) in &self.spans { encode_varint(l as u64, &mut lens); }
+        let mut names = Vec::new();
+        for name in &self.names {
+            encode_varint(name.len() as u64, &mut names);
+            names.extend_from_slice(name.as_bytes());
+        }
+        let mut lines = Vec::new();
+        if let Some(t) = &self.lines { t.write_to(&mut lines)?; }
+        for section in [starts, lens, names, lines] {
+            w.write_all(&(section.len() as u64).to_le_bytes())?;
+            w.write_all(&section)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from(r: &mut impl Read) -> Result<Self> {
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        ensure!(matches!(&magic, b"FMD1" | b"FMD2" | b"FMD3"), "bad doc table (magic)");
+        let mut b8 = [0u8; 8];
+        r.read_exact(&mut b8)?;
+        let n = u64::from_le_bytes(b8) as usize;
+        let mut section = |r: &mut dyn Read| -> Result<Vec<u8>> {
+            r.read_exact(&mut b8)?;
+            let mut buf = vec![0u8; u64::from_le_bytes(b8) as usize];
+            r.read_exact(&mut buf)?;
+            Ok(buf)
+        };
+
+        let starts = decode_u64s_varint_delta(&section(r)?)?;
+        let buf = section(r)?;
+        let mut spans = Vec::with_capacity(n);
+        let mut i = 0usize;
+        for &s in &starts {
+            let (l, j) = decode_varint(&buf, i)?;
+            i = j;
+            spans.push((s as usize, l as usize));
+        }
+        ensure!(spans.len() == n, "doc table length mismatch");
+
+        let mut names = Vec::new();
+        if &magic != b"FMD1" {
+            let buf = section(r)?;
+            let mut i = 0usize;
+            while i < buf.len() {
+                let (l, j) = decode_varint(&buf, i)?;
+                let end = j + l as usize;
+                ensure!(end <= buf.len(), "doc table names truncated");
+                names.push(String::from_utf8(buf[j..end].to_vec())?);
+                i = end;
+            }
+            ensure!(names.is_empty() || names.len() == n, "doc table names mismatch");
+        }
+        let table = Self { spans, names, lines: None };
+        if &magic == b"FMD3" {
+            let buf = section(r)?;
+            if !buf.is_empty() { return table.with_lines(LineTable::read_from(&mut buf.as_slice())?); }
+        }
+        Ok(table)
+    }
+}
+
+/// Joins named documents into one text for an FM-index, separated by
+/// `DOC_SEPARATOR`, and records where each one landed.
+pub struct CollectionWriter<W: Write> {
+    w: W,
+    pos: usize,
+    table: DocTable,
+}
+
+impl<W: Write> CollectionWriter<W> {
+    pub fn new(w: W) -> Self { Self { w, pos: 0, table: DocTable::default() } }
+
+    /// Append one document; returns its id.
+    pub fn add(&mut self, name: &str, bytes: &[u8]) -> Result<u32> {
+        if !self.table.is_empty() {
+            self.w.write_all(&[DOC_SEPARATOR])?;
+            self.pos += 1;
+        }
+        self.w.write_all(bytes)?;
+        self.table.spans.push((self.pos, bytes.len()));
+        self.table.names.push(name.to_string());
+        self.pos += bytes.len();
+        self.table.check_ids()?;
+        Ok(self.table.len() as u32 - 1)
+    }
+
+    /// The sink and the table of everything added.
+    pub fn finish(self) -> (W, DocTable) { (self.w, self.table) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collection_roundtrip() {
+        let mut c = CollectionWriter::new(Vec::new());
+        c.add("a.txt", b"alpha").unwrap();
+        c.add("b.txt", b"").unwrap();
+        c.add("c.txt", b"gamma").unwrap();
+        let (text, docs) = c.finish();
+        assert_eq!(text, b"alpha\n\ngamma");
+        assert_eq!(docs.resolve(7, 3), Some((2, 0)));
+        assert_eq!(docs.resolve(3, 4), None); // "ha\n\n" runs into the separators
+        assert_eq!((docs.label(2), docs.find("b.txt")), ("c.txt".to_string(), Some(1)));
+
+        let mut buf = Vec::new();
+        docs.write_to(&mut buf).unwrap();
+        assert_eq!(DocTable::read_from(&mut buf.as_slice()).unwrap(), docs);
+        assert_eq!(DocTable::from_lines(b"x\ny").label(1), "1");
+
+        let mut lines = LineTable::default();
+        for body in [&b"al\npha"[..], b"", b"gamma"] { lines.push_doc(body); }
+        assert!(docs.clone().with_lines(LineTable::default()).is_err());
+        let docs = docs.with_lines(lines).unwrap();
+        let mut buf = Vec::new();
+        docs.write_to(&mut buf).unwrap();
+        let loaded = DocTable::read_from(&mut buf.as_slice()).unwrap();
+        assert_eq!(loaded, docs);
+        assert_eq!(loaded.lines().unwrap().locate(0, 3, 6).map(|p| p.line), Some(2));
+    }
+}
@@ -0,0 +1,359 @@
+use crate::index::doctable::DocTable;
+use crate::index::fmindex::{FMIndex, FmxWriter, RleEncoder, TERMINATOR_SLOT};
+use crate::index::suffix_array::build_sa_ints;
+use crate::util::mmap::mmap_file;
+use crate::util::rrr::CompressedFlags;
+use crate::util::varint::{encode_varint, zigzag_encode};
+use anyhow::*;
+use rayon::prelude::*;
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, Write};
+
+/// Peak bytes per difference-cover sample while ranking the sample: the
+/// sample list, the reduced string, and SA-IS's arrays and recursion.
+const SAMPLE_BUILD_BYTES: usize = 80;
+/// Smallest read buffer per sorted run during the merge.
+const MIN_RUN_BUF: usize = 64;
+/// Bytes per run in the merge heap.
+const HEAP_SLOT: usize = 16;
+
+/// What `build_fm_external` did, for progress output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExternalBuild {
+    pub text_len: usize,
+    /// Difference cover period; suffix comparisons look at most this many bytes.
+    pub period: usize,
+    pub runs: usize,
+}
+
+/// Build an FMX file for the bytes of `text` without holding its suffix
+/// array in memory.
+///
+/// The file is memory mapped in place (the mapping is backed by the file
+/// and not counted against `mem_budget`), so it must not change meanwhile. Everything
+/// else is: the suffixes at a difference cover sample of positions are
+/// ranked with SA-IS, which lets any two suffixes be compared in at most
+/// `period` bytes. Text positions are then cut into chunks, each chunk is
+/// sorted into a run on disk, and the runs are merged in suffix order while
+/// the BWT and SA samples stream through temp files into `FmxWriter`. The
+/// output is identical to `FMIndex::build` + `with_docs` + `save`.
+pub fn build_fm_external(
+    text: &File, docs: Option<&DocTable>, out: &str, sa_sample: usize, mem_budget: usize,
+) -> Result<ExternalBuild> {
+    let sa_sample = sa_sample.max(1);
+    let dir = tempfile::Builder::new()
+        .prefix(".fmx-build")
+        .tempdir_in(std::path::Path::new(out).parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(".".as_ref()))?;
+    let map = mmap_file(text)?;
+    let t: &[u8] = &map;
+    let len = t.len();
+    FMIndex::check_text_len(len as u64 + 1)?;
+    let n = len + 1; // rows, with the virtual terminator's
+    let plan = Plan::new(len, n, sa_sample, mem_budget)?;
+
+    // C from byte counts, since the BWT is a permutation of the text
+    let mut freq = [0u64; 256];
+    for &b in t { freq[b as usize] += 1; }
+    let mut c = [0u64; 256];
+    let mut acc = 1u64; // row 0 is the terminator's
+    for (ci, f) in c.iter_mut().zip(freq) { *ci = acc; acc += f; }
+
+    let dc = DiffCover::build(t, plan.period)?;
+
+    // one pass over the positions, cut into sorted runs of `plan.chunk`
+    let mut runs = Vec::new();
+    let mut chunk = Vec::with_capacity(plan.chunk.min(len));
+    for lo in (0..len).step_by(plan.chunk) {
+        chunk.clear();
+        chunk.extend(lo..(lo + plan.chunk).min(len));
+        chunk.par_sort_unstable_by(|&a, &b| dc.cmp(t, a, b));
+        let mut f = BufWriter::with_capacity(plan.io_buf, tempfile::tempfile_in(dir.path())?);
+        for &p in &chunk { f.write_all(&(p as u64).to_le_bytes())?; }
+        let mut f = f.into_inner().map_err(|e| e.into_error())?;
+        f.rewind()?;
+        runs.push(Run { r: BufReader::with_capacity(plan.run_buf, f), left: chunk.len() });
+    }
+    drop(chunk);
+
+    // merge the runs and stream the BWT and SA samples out in SA order
+    let mut rle = RleEncoder::new(BufWriter::with_capacity(plan.io_buf, tempfile::tempfile_in(dir.path())?));
+    let mut samp = BufWriter::with_capacity(plan.io_buf, tempfile::tempfile_in(dir.path())?);
+    let mut samp_len = 0u64;
+    let mut isa_samp = vec![0u64; n.div_ceil(sa_sample)];
+    let (mut rank, mut prev_pos, mut buf) = (0usize, 0i64, Vec::new());
+    let mut primary = 0u64;
+    let n_runs = runs.len();
+    let mut emit = |pos: usize| -> Result<()> {
+        if pos == 0 { primary = rank as u64; }
+        rle.push(if pos == 0 { TERMINATOR_SLOT } else { t[pos - 1] })?;
+        if rank % sa_sample == 0 {
+            buf.clear();
+            encode_varint(zigzag_encode(pos as i64 - prev_pos), &mut buf);
+            samp.write_all(&buf)?;
+            samp_len += buf.len() as u64;
+            prev_pos = pos as i64;
+        }
+        if pos.is_multiple_of(sa_sample) { isa_samp[pos / sa_sample] = rank as u64; }
+        rank += 1;
+        Ok(())
+    };
+    // the terminator's empty suffix sorts first
+    emit(len)?;
+    merge_runs(runs, |a, b| dc.cmp(t, a, b) == Ordering::Less, &mut emit)?;
+    debug_assert_eq!(rank, n);
+    drop(dc);
+
+    let mut w = FmxWriter::create(out, n, sa_sample, Some(primary), &c)?;
+    let mut rle = rle.finish()?.into_inner().map_err(|e| e.into_error())?;
+    let rle_len = rle.stream_position()?;
+    rle.rewind()?;
+    w.section(&mut rle, rle_len)?;
+    let ones: Vec<usize> = (0..n).step_by(sa_sample).collect();
+    w.flags(&CompressedFlags::build(n, &ones))?;
+    drop(ones);
+    let mut samp = samp.into_inner().map_err(|e| e.into_error())?;
+    samp.rewind()?;
+    w.section(&mut samp, samp_len)?;
+    w.isa_samp(&isa_samp)?;
+    w.finish(docs)?;
+    Ok(ExternalBuild { text_len: len, period: plan.period, runs: n_runs })
+}
+
+/// Sizes chosen so that each phase of the build fits the budget.
+#[derive(Debug)]
+struct Plan {
+    period: usize,
+    /// Positions per sorted run.
+    chunk: usize,
+    run_buf: usize,
+    io_buf: usize,
+}
+
+impl Plan {
+    /// Pick the smallest difference cover period whose phases all fit:
+    /// ranking the sample, sorting a chunk next to the sample ranks, merging
+    /// next to the ranks and the ISA samples, and building the sample flags.
+    fn new(len: usize, n: usize, sa_sample: usize, budget: usize) -> Result<Self> {
+        let io_buf = (budget / 64).clamp(256, 1 << 16);
+        let n_samples = n.div_ceil(sa_sample);
+        let isa = n_samples * 8;
+        // `ones`, then CompressedFlags' positions and superblock counts
+        let flags = n_samples * 16 + (n / 512 + 2) * 8;
+        let too_small = || anyhow!("memory budget of {budget} bytes is too small for a {len}-byte text");
+        ensure!(isa + flags <= budget, too_small());
+        for period in (4..=16).map(|k| 1usize << k) {
+            let sample = DiffCover::sample_len(len, period);
+            let ranks = sample * 4;
+            if sample * SAMPLE_BUILD_BYTES > budget { continue; }
+            let chunk = budget.saturating_sub(ranks + io_buf) / 8;
+            if chunk == 0 { continue; }
+            let runs = len.div_ceil(chunk).max(1);
+            let per_run = budget.saturating_sub(ranks + isa + 2 * io_buf) / runs;
+            if per_run < MIN_RUN_BUF + HEAP_SLOT { continue; }
+            return Ok(Self { period, chunk, run_buf: (per_run - HEAP_SLOT).min(1 << 16), io_buf });
+        }
+        Err(too_small())
+    }
+}
+
+/// Suffix ranks at a difference cover sample of positions.
+///
+/// `cover` is a set D of residues mod `period` such that every difference
+/// mod `period` is `b - a` for some a, b in D. So for any two positions
+/// there is a shift k < `period` that lands both on sampled positions, and
+/// their suffixes compare as the k bytes before that plus the two ranks.
+struct DiffCover {
+    period: usize,
+    len: usize,
+    /// For each difference d, an a in D with a + d in D (mod period).
+    lift: Vec<usize>,
+    /// Per residue in D, where its positions start in `rank`.
+    slot: Vec<usize>,
+    rank: Vec<u32>,
+}
+
+impl DiffCover {
+    /// {0..r} plus the multiples of r, r = ceil(sqrt(period)): d = q*r + s
+    /// is (q+1)*r - (r-s), or q*r - 0 when s == 0.
+    fn cover(period: usize) -> Vec<usize> {
+        let r = (1..).find(|r| r * r >= period).unwrap();
+        let mut d: Vec<usize> = (0..r).chain((1..=period.div_ceil(r)).map(|k| k * r % period)).collect();
+        d.sort_unstable();
+        d.dedup();
+        d
+    }
+
+    /// Positions in the sample for a text of `len` bytes, plus one end
+    /// marker per residue.
+    fn sample_len(len: usize, period: usize) -> usize {
+        Self::cover(period).iter().map(|&a| if a < len { (len - 1 - a) / period + 1 } else { 0 } + 1).sum()
+    }
+
+    /// Rank the sampled suffixes: name each sampled position by the `period`
+    /// bytes starting there, lay the names out residue by residue (so a
+    /// suffix of that string reads suffix `p` of the text in steps of
+    /// `period`), and suffix sort it.
+    fn build(t: &[u8], period: usize) -> Result<Self> {
+        let len = t.len();
+        let cover = Self::cover(period);
+        let mut in_cover = vec![false; period];
+        for &a in &cover { in_cover[a] = true; }
+        let lift: Vec<usize> = (0..period)
+            .map(|d| cover.iter().copied().find(|&a| in_cover[(a + d) % period]).expect("difference cover"))
+            .collect();
+
+        let mut slot = vec![usize::MAX; period];
+        let mut total = 0usize;
+        for &a in &cover {
+            slot[a] = total;
+            total += if a < len { (len - 1 - a) / period + 1 } else { 0 } + 1;
+        }
+        ensure!(total < u32::MAX as usize, "text of {len} bytes is too large for the external build");
+
+        // the end marker after residue j's positions is named j; text
+        // prefixes get names after those, in order
+        let mut sample: Vec<usize> = (0..len).filter(|&p| in_cover[p % period]).collect();
+        let prefix = |p: usize| &t[p..(p + period).min(len)];
+        sample.par_sort_unstable_by(|&a, &b| prefix(a).cmp(prefix(b)));
+        let mut reduced = vec![0u32; total];
+        for (j, &a) in cover.iter().enumerate() {
+            let end = slot[a] + if a < len { (len - 1 - a) / period + 1 } else { 0 };
+            reduced[end] = j as u32;
+        }
+        let mut name = cover.len() as u32;
+        for (x, &p) in sample.iter().enumerate() {
+            if x > 0 && prefix(sample[x - 1]) != prefix(p) { name += 1; }
+            reduced[slot[p % period] + p / period] = name;
+        }
+        drop(sample);
+
+        let sa = build_sa_ints(&reduced, name as usize + 1);
+        for (r, &i) in sa.iter().enumerate() { reduced[i] = r as u32; }
+        Ok(Self { period, len, lift, slot, rank: reduced })
+    }
+
+    /// Rank of sampled suffix `p`; the empty suffix at `len` is below all.
+    #[inline]
+    fn rank_of(&self, p: usize) -> u32 {
+        if p == self.len { 0 } else { self.rank[self.slot[p % self.period] + p / self.period] }
+    }
+
+    fn cmp(&self, t: &[u8], i: usize, j: usize) -> Ordering {
+        if i == j { return Ordering::Equal; }
+        let per = self.period;
+        let a = self.lift[(j % per + per - i % per) % per];
+        let k = (a + per - i % per) % per;
+        let (ei, ej) = (i + k, j + k);
+        // a suffix that ends within k bytes is shorter, so this cannot tie
+        match t[i..ei.min(self.len)].cmp(&t[j..ej.min(self.len)]) {
+            Ordering::Equal => self.rank_of(ei).cmp(&self.rank_of(ej)),
+            o => o,
+        }
+    }
+}
+
+/// A sorted run of positions on disk.
+struct Run {
+    r: BufReader<File>,
+    left: usize,
+}
+
+impl Run {
+    fn next(&mut self) -> Result<Option<usize>> {
+        if self.left == 0 { return Ok(None); }
+        self.left -= 1;
+        let mut b8 = [0u8; 8];
+        self.r.read_exact(&mut b8)?;
+        Ok(Some(u64::from_le_bytes(b8) as usize))
+    }
+}
+
+/// K-way merge of sorted runs with a binary heap ordered by `less`.
+fn merge_runs(
+    mut runs: Vec<Run>, less: impl Fn(usize, usize) -> bool, emit: &mut impl FnMut(usize) -> Result<()>,
+) -> Result<()> {
+    let sift_down = |heap: &mut [(usize, usize)], mut i: usize| loop {
+        let l = 2 * i + 1;
+        if l >= heap.len() { break; }
+        let c = if l + 1 < heap.len() && less(heap[l + 1].0, heap[l].0) { l + 1 } else { l };
+        if !less(heap[c].0, heap[i].0) { break; }
+        heap.swap(i, c);
+        i = c;
+    };
+    let mut heap = Vec::with_capacity(runs.len());
+    for (x, run) in runs.iter_mut().enumerate() {
+        if let Some(p) = run.next()? { heap.push((p, x)); }
+    }
+    for i in (0..heap.len() / 2).rev() { sift_down(&mut heap, i); }
+    while let Some(&(pos, x)) = heap.first() {
+        emit(pos)?;
+        match runs[x].next()? {
+            Some(p) => heap[0] = (p, x),
+            None => {
+                let last = heap.pop().unwrap();
+                if heap.is_empty() { break; }
+                heap[0] = last;
+            }
+        }
+        sift_down(&mut heap, 0);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::suffix_array::build_sa;
+
+    fn same_as_in_memory(text: &[u8], sa_sample: usize, budget: usize) -> ExternalBuild {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("a.fm"), dir.path().join("b.fm"));
+
+        let docs = DocTable::from_lines(text);
+        FMIndex::build(text, &build_sa(text), sa_sample).with_docs(docs).save(a.to_str().unwrap()).unwrap();
+
+        let docs = DocTable::from_lines(text);
+        let input = dir.path().join("text");
+        std::fs::write(&input, text).unwrap();
+        let stats = build_fm_external(&File::open(&input).unwrap(), Some(&docs), b.to_str().unwrap(), sa_sample, budget).unwrap();
+        assert_eq!(std::fs::read(&a).unwrap(), std::fs::read(&b).unwrap());
+        assert_eq!(FMIndex::load(b.to_str().unwrap()).unwrap().docs(), Some(&docs));
+        stats
+    }
+
+    #[test]
+    fn same_file_as_in_memory_build() {
+        let text = b"the quick brown fox jumps over the lazy dog\nthe dog sleeps\n".repeat(1024);
+        // a budget this small forces many runs
+        let stats = same_as_in_memory(&text, 32, 160_000);
+        assert!(stats.runs > 2, "{stats:?}");
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("text");
+        std::fs::write(&input, &text).unwrap();
+        let out = dir.path().join("unused.fm");
+        assert!(build_fm_external(&File::open(&input).unwrap(), None, out.to_str().unwrap(), 4, 1000).is_err());
+    }
+
+    #[test]
+    fn repetitive_text() {
+        // every suffix shares a long prefix with its neighbours
+        let mut text = vec![b'a'; 40_000];
+        text.extend(b"ab".repeat(20_000));
+        text.extend(b"a\na");
+        let stats = same_as_in_memory(&text, 32, 300_000);
+        assert!(stats.runs > 2, "{stats:?}");
+    }
+
+    #[test]
+    fn cover_hits_every_difference() {
+        for period in (4..=16).map(|k| 1usize << k) {
+            let d = DiffCover::cover(period);
+            let mut hit = vec![false; period];
+            for &a in &d {
+                for &b in &d { hit[(b + period - a) % period] = true; }
+            }
+            assert!(hit.iter().all(|&h| h), "{period}");
+        }
+    }
+}
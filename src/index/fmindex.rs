@@ -1,44 +1,74 @@
-use crate::util::bitvec::RankBitVec;
+use crate::index::doctable::DocTable;
 use crate::util::rrr::CompressedFlags;
-use crate::util::varint::{decode_varint, encode_varint};
+use crate::util::wavelet::WaveletMatrix;
+use crate::util::varint::{decode_varint, encode_varint, zigzag_decode, zigzag_encode};
 use anyhow::*;
 use std::fs::File;
-use std::io::{Read, Write};
-
+use std::io::{BufWriter, Read, Write};
+
+/// FM-index over a text plus a virtual terminator that sorts before every
+/// byte, so any byte (NUL and `$` included) can appear in the text. Rows are
+/// `n = text_len + 1`: row 0 is the terminator's suffix, and the BWT slot of
+/// the row for text position 0 (`primary`) holds a placeholder that rank
+/// ignores. Indexes loaded from FMX2-4 files have no `primary`: their text
+/// ended in a real `$`, which stands in for the terminator.
 pub struct FMIndex {
     pub c: [u64; 256],
-    pub occ: Vec<RankBitVec>,    // rebuilt from BWT on load
-    pub bwt: Vec<u8>,            // stored compressed-on-disk (RLE+varint)
+    pub occ: WaveletMatrix,      // BWT with access/rank; stored RLE+varint on disk
     pub n: usize,
     pub sa_sample: usize,
+    primary: Option<u64>,        // row whose BWT slot is the virtual terminator
 
     // Compressed SA sampling flags + positions
     samp_flags: CompressedFlags, // rank-accelerated sparse structure
     samp_pos: Vec<usize>,        // SA positions for sampled ranks (rank order)
+    isa_samp: Vec<u64>,          // rows of text positions 0, s, 2s, ... (inverse SA)
+    docs: Option<DocTable>,      // document spans and names, when the text is a collection
 }
 
+/// Byte stored in the BWT slot of the virtual terminator; never counted.
+pub(crate) const TERMINATOR_SLOT: u8 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct MatchRange { pub l: u64, pub r: u64 }
 
+/// Error model for `approx_search`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApproxMode {
+    /// Substitutions only; matches have the pattern's length.
+    Hamming,
+    /// Substitutions, insertions and deletions (Levenshtein).
+    Edit,
+}
+
+/// Rows of one matched text string with its alignment cost and its length
+/// in text bytes (which differs from the pattern's under `ApproxMode::Edit`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApproxMatch { pub range: MatchRange, pub cost: u32, pub len: usize }
+
 impl FMIndex {
-    pub fn build(text: &[u8], sa: &[usize], bwt: &[u8], sa_sample: usize) -> Self {
-        let n = bwt.len();
+    /// Build over `text` given its suffix array (`build_sa(text)`, no
+    /// terminator appended).
+    pub fn build(text: &[u8], sa: &[usize], sa_sample: usize) -> Self {
+        debug_assert_eq!(sa.len(), text.len());
+        let n = text.len() + 1;
         let sa_sample = sa_sample.max(1);
 
-        // C array
+        // C array; row 0 belongs to the terminator
         let mut freq = [0u64; 256];
-        for &ch in bwt { freq[ch as usize] += 1; }
+        for &ch in text { freq[ch as usize] += 1; }
         let mut c = [0u64; 256];
-        let mut acc = 0u64;
+        let mut acc = 1u64;
         for i in 0..256 { c[i] = acc; acc += freq[i]; }
 
-        // occ bitvectors (in-memory)
-        let words = (n + 63) / 64;
-        let mut mats: Vec<Vec<u64>> = vec![vec![0u64; words]; 256];
-        for (i, &ch) in bwt.iter().enumerate() {
-            let w = i >> 6; let b = i & 63;
-            mats[ch as usize][w] |= 1u64 << b;
-        }
-        let occ = mats.into_iter().map(RankBitVec::from_bits).collect::<Vec<_>>();
+        // full SA: the terminator's (empty) suffix first
+        let sa: Vec<usize> = std::iter::once(text.len()).chain(sa.iter().copied()).collect();
+        let mut primary = 0u64;
+        let bwt: Vec<u8> = sa.iter().enumerate().map(|(r, &p)| match p {
+            0 => { primary = r as u64; TERMINATOR_SLOT }
+            _ => text[p - 1],
+        }).collect();
+        let occ = WaveletMatrix::from_bytes(&bwt);
 
         // Sample flags + positions
         let mut one_positions = Vec::<usize>::new();
@@ -51,29 +81,66 @@ impl FMIndex {
         }
         let samp_flags = CompressedFlags::build(n, &one_positions);
 
-        debug_assert_eq!(text.last().copied(), Some(b"$"[0]));
+        // Inverse-SA samples: row of every sa_sample-th text position
+        let mut isa_samp = vec![0u64; n.div_ceil(sa_sample)];
+        for (rank, &pos) in sa.iter().enumerate() {
+            if pos % sa_sample == 0 { isa_samp[pos / sa_sample] = rank as u64; }
+        }
+
+        Self { c, occ, n, sa_sample, primary: Some(primary), samp_flags, samp_pos, isa_samp, docs: None }
+    }
+
+    /// Attach the doc table saved with the index.
+    pub fn with_docs(mut self, docs: DocTable) -> Self {
+        self.docs = Some(docs);
+        self
+    }
+
+    /// Documents of the text, from the FMX file or a legacy `.docs` sidecar.
+    pub fn docs(&self) -> Option<&DocTable> { self.docs.as_ref() }
+
+    /// Text length, without the terminator.
+    pub fn text_len(&self) -> usize { self.n - 1 }
+
+    /// 1 if the terminator slot lies in rows `0..i` and would be counted as `ch`.
+    #[inline]
+    fn slot_adjust(&self, ch: u8, i: u64) -> u64 {
+        self.primary.is_some_and(|p| ch == TERMINATOR_SLOT && p < i) as u64
+    }
 
-        Self { c, occ, bwt: bwt.to_vec(), n, sa_sample, samp_flags, samp_pos }
+    #[inline] fn occ_rank(&self, ch: u8, i: u64) -> u64 { self.occ.rank(ch, i as usize) - self.slot_adjust(ch, i) }
+
+    /// LF step plus the BWT byte it crossed (the text byte before row `r`).
+    /// From `primary` (text position 0) it wraps to row 0, the terminator.
+    #[inline]
+    fn lf_with_char(&self, r: u64) -> (u64, u8) {
+        if self.primary == Some(r) { return (0, TERMINATOR_SLOT); }
+        let (ch, k) = self.occ.access_rank(r as usize);
+        (self.c[ch as usize] + k - self.slot_adjust(ch, r), ch)
     }
 
-    #[inline] fn occ_rank(&self, ch: u8, i: u64) -> u64 { self.occ[ch as usize].rank1(i as usize) }
+    #[inline]
+    fn lf(&self, r: u64) -> u64 { self.lf_with_char(r).0 }
+
+    /// Range of all rows (the empty pattern).
+    pub fn full_range(&self) -> MatchRange { MatchRange { l: 0, r: self.n as u64 } }
 
+    /// One backward-search step: rows whose suffix is `ch` followed by the
+    /// pattern of `range`, or None if there are none.
     #[inline]
-    fn lf(&self, r: u64) -> u64 {
-        let ch = self.bwt[r as usize];
-        self.c[ch as usize] + self.occ_rank(ch, r)
+    pub fn extend_left(&self, range: &MatchRange, ch: u8) -> Option<MatchRange> {
+        let base = self.c[ch as usize];
+        let l = base + self.occ_rank(ch, range.l);
+        let r = base + self.occ_rank(ch, range.r);
+        (l < r).then_some(MatchRange { l, r })
     }
 
     pub fn backward_search(&self, pat: &[u8]) -> Option<MatchRange> {
-        if pat.is_empty() { return Some(MatchRange{ l:0, r:self.n as u64 }); }
-        let mut l = 0u64; let mut r = self.n as u64;
+        let mut range = self.full_range();
         for &ch in pat.iter().rev() {
-            let base = self.c[ch as usize];
-            l = base + self.occ_rank(ch, l);
-            r = base + self.occ_rank(ch, r);
-            if l >= r { return None; }
+            range = self.extend_left(&range, ch)?;
         }
-        Some(MatchRange { l, r })
+        Some(range)
     }
 
     #[inline]
@@ -109,87 +176,234 @@ impl FMIndex {
         (range.l as usize..range.l as usize + take).map(|rr| self.locate(rr as u64)).collect()
     }
 
-    // -------- serialization (v2) ----------
+    /// First-column byte of row `r`: the byte whose C-bucket holds `r`.
+    #[inline]
+    fn f_char(&self, r: u64) -> u8 {
+        (self.c.partition_point(|&c| c <= r) - 1) as u8
+    }
+
+    /// Inverse of LF: the row of the suffix one position to the right. The
+    /// `k`-th occurrence of `F[r]` in the BWT is found by binary search on rank.
+    fn psi(&self, r: u64) -> u64 {
+        let ch = self.f_char(r);
+        let k = r - self.c[ch as usize];
+        let (mut lo, mut hi) = (0u64, self.n as u64);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            if self.occ_rank(ch, mid + 1) > k { hi = mid; } else { lo = mid + 1; }
+        }
+        lo
+    }
+
+    /// The `len` text bytes preceding the suffix of row `r`, in text order,
+    /// read off the BWT by LF steps. Stops early at the text start.
+    pub fn bytes_before(&self, mut r: u64, len: usize) -> Vec<u8> {
+        let mut out = Vec::with_capacity(len);
+        for _ in 0..len {
+            if self.primary == Some(r) { break; }
+            let (next, ch) = self.lf_with_char(r);
+            out.push(ch);
+            r = next;
+        }
+        out.reverse();
+        out
+    }
+
+    /// The first `len` bytes of the suffix of row `r`, read off the first
+    /// column by psi steps. Stops early at the text end.
+    pub fn bytes_after(&self, mut r: u64, len: usize) -> Vec<u8> {
+        let mut out = Vec::with_capacity(len);
+        for _ in 0..len {
+            if self.primary.is_some() && r == 0 { break; }
+            out.push(self.f_char(r));
+            r = self.psi(r);
+        }
+        out
+    }
+
+    /// Text bytes `start..start + len`, decoded from the index alone: LF steps
+    /// back from the nearest inverse-SA sample at or after the end, so at most
+    /// `len + sa_sample` steps.
+    pub fn extract(&self, start: usize, len: usize) -> Result<Vec<u8>> {
+        let end = start.checked_add(len).filter(|&e| e <= self.text_len());
+        let Some(end) = end else { bail!("extract {start}+{len} is past the text end ({})", self.text_len()) };
+        let j = end.div_ceil(self.sa_sample);
+        // past the last sample, position n is position 0 again (the text is cyclic)
+        let (mut pos, mut r) = match self.isa_samp.get(j) {
+            Some(&r) => (j * self.sa_sample, r),
+            None => (self.n, self.isa_samp[0]),
+        };
+        while pos > end {
+            r = self.lf(r);
+            pos -= 1;
+        }
+        Ok(self.bytes_before(r, len))
+    }
+
+    /// Approximate backward search: every text string within `k` errors of
+    /// `pat`, found by backtracking over `extend_left` steps from the
+    /// pattern's last byte. Each string is reported once, with its cheapest
+    /// alignment, sorted by (cost, len, range). Under `Edit`, text insertions are
+    /// not allowed at the right end of the match, which only ever produces
+    /// longer copies of matches already reported. Fails once more than
+    /// `max_nodes` search nodes were visited.
+    pub fn approx_search(&self, pat: &[u8], k: u32, mode: ApproxMode, max_nodes: usize) -> Result<Vec<ApproxMatch>> {
+        let mut best: std::collections::HashMap<(MatchRange, usize), u32> = Default::default();
+        let mut nodes = 0usize;
+        self.approx_step(pat, pat.len(), self.full_range(), 0, 0, k, mode, &mut best, (&mut nodes, max_nodes))?;
+        let mut out: Vec<ApproxMatch> =
+            best.into_iter().map(|((range, len), cost)| ApproxMatch { range, cost, len }).collect();
+        out.sort_by_key(|m| (m.cost, m.len, m.range.l, m.range.r));
+        Ok(out)
+    }
+
+    /// `approx_search` hits as `(offset, len, cost)`, one per start offset
+    /// with its cheapest alignment (the shortest on ties), sorted by offset.
+    /// Hits that do not resolve to a document (when the index has a doc
+    /// table) are dropped, as are shifted copies lying inside a cheaper hit
+    /// ("etwork" in "network"). Matches are located cheapest first until
+    /// `limit` offsets are found, so with more, these are among the cheapest.
+    pub fn approx_locate(
+        &self, pat: &[u8], k: u32, mode: ApproxMode, limit: usize, max_nodes: usize,
+    ) -> Result<Vec<(usize, usize, u32)>> {
+        let mut best: std::collections::BTreeMap<usize, (usize, u32)> = Default::default();
+        'matches: for m in self.approx_search(pat, k, mode, max_nodes)? {
+            for r in m.range.l..m.range.r {
+                if best.len() >= limit {
+                    break 'matches;
+                }
+                let off = self.locate(r);
+                if self.docs().is_some_and(|docs| docs.resolve(off, m.len).is_none()) {
+                    continue;
+                }
+                best.entry(off).or_insert((m.len, m.cost));
+            }
+        }
+        let mut kept: Vec<(usize, usize, u32)> = Vec::with_capacity(best.len());
+        for h in best.into_iter().map(|(off, (len, cost))| (off, len, cost)) {
+            let inside = kept.last().is_some_and(|p| h.0 + h.1 <= p.0 + p.1 && h.2 >= p.2);
+            if !inside { kept.push(h); }
+        }
+        Ok(kept)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn approx_step(
+        &self, pat: &[u8], i: usize, range: MatchRange, len: usize, cost: u32, k: u32, mode: ApproxMode,
+        best: &mut std::collections::HashMap<(MatchRange, usize), u32>,
+        (nodes, max_nodes): (&mut usize, usize),
+    ) -> Result<()> {
+        if i == 0 {
+            if len > 0 {
+                let e = best.entry((range, len)).or_insert(cost);
+                *e = (*e).min(cost);
+            }
+            return Ok(());
+        }
+        *nodes += 1;
+        ensure!(*nodes <= max_nodes, "approximate search visited more than {max_nodes} nodes");
+        let want = pat[i - 1];
+        if mode == ApproxMode::Edit && cost < k {
+            // deletion: pattern byte with no text byte
+            self.approx_step(pat, i - 1, range, len, cost + 1, k, mode, best, (&mut *nodes, max_nodes))?;
+        }
+        for ch in 0..=255u8 {
+            let Some(next) = self.extend_left(&range, ch) else { continue };
+            let sub = cost + (ch != want) as u32;
+            if sub <= k {
+                self.approx_step(pat, i - 1, next, len + 1, sub, k, mode, best, (&mut *nodes, max_nodes))?;
+            }
+            if mode == ApproxMode::Edit && cost < k && i < pat.len() {
+                // insertion: text byte with no pattern byte
+                self.approx_step(pat, i, next, len + 1, cost + 1, k, mode, best, (&mut *nodes, max_nodes))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Refuse texts this build cannot index: every position and the row
+    /// range end `n` must fit in `usize`. All on-disk fields are 64-bit (or
+    /// varints), so on 64-bit hosts this only rejects the impossible.
+    pub fn check_text_len(n: u64) -> Result<()> {
+        ensure!(n < usize::MAX as u64, "text of {n} bytes is too large for this platform (max {})", usize::MAX - 1);
+        Ok(())
+    }
+
+    // -------- serialization (v7) ----------
 
     /// Save to disk with:
-    /// magic "FMX2"
-    /// u64 n, u64 sa_sample
+    /// magic "FMX7"
+    /// u64 n, u64 sa_sample, u64 primary (u64::MAX: no virtual terminator; FMX2-4 lack the field)
     /// C[256]*u64
     /// RLE-BWT: u64 rle_len, [ (u8 symbol, varint run_len) ... ]
-    /// CompressedFlags (nbits, n_super, super[u32], n_ones, ones[u32])
-    /// samp_pos varint-delta (u64)
+    /// CompressedFlags (nbits, width, n_super, super[width], n_ones, ones[width]);
+    ///   width is 4 bytes up to u32::MAX bits and 8 beyond (FMX2/3: always u32, no width byte)
+    /// samp_pos in rank order as zigzag varint deltas (FMX2-6: wrapping u64 deltas)
+    /// isa_samp varint (u64 byte len)
+    /// u8 has_docs, then a DocTable ("FMD3" ...) if 1 (FMX6 and later)
     pub fn save(&self, path: &str) -> Result<()> {
-        let mut f = File::create(path)?;
-
-        // magic
-        f.write_all(b"FMX2")?;
-
-        // header
-        f.write_all(&(self.n as u64).to_le_bytes())?;
-        f.write_all(&(self.sa_sample as u64).to_le_bytes())?;
-
-        // C
-        for &v in &self.c { f.write_all(&v.to_le_bytes())?; }
+        let mut w = FmxWriter::create(path, self.n, self.sa_sample, self.primary, &self.c)?;
 
         // BWT (RLE + varint)
-        let rle = Self::encode_bwt_rle(&self.bwt);
-        f.write_all(&(rle.len() as u64).to_le_bytes())?;
-        f.write_all(&rle)?;
+        let mut rle = RleEncoder::new(Vec::new());
+        for i in 0..self.n { rle.push(self.occ.access(i))?; }
+        let rle = rle.finish()?;
+        w.section(&mut rle.as_slice(), rle.len() as u64)?;
 
         // compressed flags
-        self.samp_flags.save(&mut f)?;
+        w.flags(&self.samp_flags)?;
 
-        // samp_pos delta+varint (in rank order)
+        // samp_pos in rank order: positions are not monotonic, so deltas are signed
         let mut buf = Vec::new();
-        let mut acc = 0u64;
-        for (i, &p) in self.samp_pos.iter().enumerate() {
-            let v = p as u64;
-            let delta = if i == 0 { v } else { v - acc };
-            encode_varint(delta, &mut buf);
-            acc = v;
-        }
-        f.write_all(&(buf.len() as u64).to_le_bytes())?;
-        f.write_all(&buf)?;
-        Ok(())
+        let mut acc = 0i64;
+        for &p in &self.samp_pos {
+            encode_varint(zigzag_encode(p as i64 - acc), &mut buf);
+            acc = p as i64;
+        }
+        w.section(&mut buf.as_slice(), buf.len() as u64)?;
+
+        w.isa_samp(&self.isa_samp)?;
+        w.finish(self.docs.as_ref())
     }
 
     pub fn load(path: &str) -> Result<Self> {
         let mut f = File::open(path)?;
         let mut magic = [0u8; 4];
         f.read_exact(&mut magic)?;
-        ensure!(&magic == b"FMX2", "bad FM-index file (magic)");
+        ensure!(matches!(&magic, b"FMX2" | b"FMX3" | b"FMX4" | b"FMX5" | b"FMX6" | b"FMX7"), "bad FM-index file (magic)");
 
         // n, sample
         let mut b8 = [0u8; 8];
         f.read_exact(&mut b8)?;
-        let n = u64::from_le_bytes(b8) as usize;
+        let n = u64::from_le_bytes(b8);
+        Self::check_text_len(n)?;
+        let n = n as usize;
         f.read_exact(&mut b8)?;
         let sa_sample = u64::from_le_bytes(b8) as usize;
+        let primary = match &magic {
+            b"FMX5" | b"FMX6" | b"FMX7" => { f.read_exact(&mut b8)?; Some(u64::from_le_bytes(b8)).filter(|&p| p != u64::MAX) }
+            _ => None,
+        };
 
         // C
         let mut c = [0u64; 256];
-        for i in 0..256 { f.read_exact(&mut b8)?; c[i] = u64::from_le_bytes(b8); }
+        for v in c.iter_mut() { f.read_exact(&mut b8)?; *v = u64::from_le_bytes(b8); }
 
         // BWT (RLE)
         f.read_exact(&mut b8)?;
         let rle_len = u64::from_le_bytes(b8) as usize;
         let mut rle = vec![0u8; rle_len];
         f.read_exact(&mut rle)?;
-        let bwt = Self::decode_bwt_rle(&rle, n)?;
-
-        // Rebuild occ from BWT
-        let words = (bwt.len() + 63) / 64;
-        let mut mats: Vec<Vec<u64>> = vec![vec![0u64; words]; 256];
-        for (i, &ch) in bwt.iter().enumerate() {
-            let w = i >> 6; let b = i & 63;
-            mats[ch as usize][w] |= 1u64 << b;
-        }
-        let occ = mats.into_iter().map(RankBitVec::from_bits).collect::<Vec<_>>();
+        // the decoded BWT only lives until the wavelet matrix is built
+        let occ = WaveletMatrix::from_bytes(&Self::decode_bwt_rle(&rle, n)?);
 
         // compressed flags
-        let samp_flags = CompressedFlags::load(&mut f)?;
+        let samp_flags = match &magic {
+            b"FMX4" | b"FMX5" | b"FMX6" | b"FMX7" => CompressedFlags::load(&mut f)?,
+            _ => CompressedFlags::load_u32(&mut f)?,
+        };
 
-        // samp_pos varint+delta
+        // samp_pos varint deltas: zigzag since FMX7, wrapping u64 before
         f.read_exact(&mut b8)?;
         let vlen = u64::from_le_bytes(b8) as usize;
         let mut vbuf = vec![0u8; vlen];
@@ -199,31 +413,63 @@ impl FMIndex {
         let mut samp_pos = Vec::<usize>::new();
         while i < vbuf.len() {
             let (d, j) = decode_varint(&vbuf, i)?;
-            i = j; acc += d; samp_pos.push(acc as usize);
+            i = j;
+            acc = match &magic {
+                b"FMX7" => acc.wrapping_add(zigzag_decode(d) as u64),
+                _ => acc.wrapping_add(d),
+            };
+            samp_pos.push(acc as usize);
         }
 
-        Ok(Self { c, occ, bwt, n, sa_sample: sa_sample.max(1), samp_flags, samp_pos })
-    }
+        let mut fm = Self { c, occ, n, sa_sample: sa_sample.max(1), primary, samp_flags, samp_pos, isa_samp: Vec::new(), docs: None };
+        if &magic != b"FMX2" {
+            f.read_exact(&mut b8)?;
+            let vlen = u64::from_le_bytes(b8) as usize;
+            let mut vbuf = vec![0u8; vlen];
+            f.read_exact(&mut vbuf)?;
+            let mut i = 0usize;
+            while i < vbuf.len() {
+                let (r, j) = decode_varint(&vbuf, i)?;
+                i = j; fm.isa_samp.push(r);
+            }
+            ensure!(fm.isa_samp.len() == fm.n.div_ceil(fm.sa_sample), "bad FM-index file (inverse SA samples)");
+        } else {
+            fm.isa_samp = fm.rebuild_isa_samples();
+        }
 
-    // ---- BWT RLE (symbol, run_len varint) ----
-    fn encode_bwt_rle(bwt: &[u8]) -> Vec<u8> {
-        if bwt.is_empty() { return vec![]; }
-        let mut out = Vec::new();
-        let mut cur = bwt[0];
-        let mut run: u64 = 1;
-        for &ch in &bwt[1..] {
-            if ch == cur && run < u64::MAX/2 {
-                run += 1;
-            } else {
-                out.push(cur);
-                encode_varint(run, &mut out);
-                cur = ch; run = 1;
+        let mut has_docs = [0u8; 1];
+        if matches!(&magic, b"FMX6" | b"FMX7") {
+            f.read_exact(&mut has_docs)?;
+        }
+        fm.docs = match has_docs[0] {
+            1 => Some(DocTable::read_from(&mut std::io::BufReader::new(f))?),
+            _ => {
+                let sidecar = DocTable::sidecar_path(path);
+                match std::path::Path::new(&sidecar).exists() {
+                    true => Some(DocTable::load(&sidecar)?),
+                    false => None,
+                }
             }
+        };
+        Ok(fm)
+    }
+
+    /// Inverse-SA samples for FMX2 files, which predate them: one full LF walk
+    /// from a sampled row of known position visits every position's row.
+    fn rebuild_isa_samples(&self) -> Vec<u64> {
+        let mut isa_samp = vec![0u64; self.n.div_ceil(self.sa_sample)];
+        let Some(&pos0) = self.samp_pos.first() else { return isa_samp };
+        // row 0 is always sampled, so it is where samp_pos[0] lives
+        let (mut pos, mut r) = (pos0, 0u64);
+        for _ in 0..self.n {
+            if pos % self.sa_sample == 0 { isa_samp[pos / self.sa_sample] = r; }
+            r = self.lf(r);
+            pos = if pos == 0 { self.n - 1 } else { pos - 1 };
         }
-        out.push(cur);
-        encode_varint(run, &mut out);
-        out
+        isa_samp
     }
+
+    // ---- BWT RLE (symbol, run_len varint) ----
     fn decode_bwt_rle(buf: &[u8], n: usize) -> Result<Vec<u8>> {
         let mut i = 0usize;
         let mut out = Vec::with_capacity(n);
@@ -237,3 +483,162 @@ impl FMIndex {
         Ok(out)
     }
 }
+
+/// Writes an FMX file section by section, in the order `save` documents:
+/// header and C on creation, then the RLE-BWT, flags, samp_pos and isa_samp.
+/// Length-prefixed sections are copied from readers, so builders can stream
+/// them from temp files.
+pub struct FmxWriter { f: BufWriter<File> }
+
+impl FmxWriter {
+    pub fn create(path: &str, n: usize, sa_sample: usize, primary: Option<u64>, c: &[u64; 256]) -> Result<Self> {
+        let mut f = BufWriter::new(File::create(path)?);
+        f.write_all(b"FMX7")?;
+        f.write_all(&(n as u64).to_le_bytes())?;
+        f.write_all(&(sa_sample as u64).to_le_bytes())?;
+        f.write_all(&primary.unwrap_or(u64::MAX).to_le_bytes())?;
+        for &v in c { f.write_all(&v.to_le_bytes())?; }
+        Ok(Self { f })
+    }
+
+    /// A u64 byte length followed by `len` bytes from `r`.
+    pub fn section(&mut self, r: &mut impl Read, len: u64) -> Result<()> {
+        self.f.write_all(&len.to_le_bytes())?;
+        let copied = std::io::copy(&mut r.take(len), &mut self.f)?;
+        ensure!(copied == len, "FMX section short by {} bytes", len - copied);
+        Ok(())
+    }
+
+    pub fn flags(&mut self, flags: &CompressedFlags) -> Result<()> { flags.save(&mut self.f) }
+
+    /// Inverse-SA samples as plain varints (rows are not monotonic).
+    pub fn isa_samp(&mut self, rows: &[u64]) -> Result<()> {
+        let mut buf = Vec::new();
+        for &r in rows { encode_varint(r, &mut buf); }
+        self.section(&mut buf.as_slice(), buf.len() as u64)
+    }
+
+    /// Last section: the doc table, if any. Flushes the file.
+    pub fn finish(mut self, docs: Option<&DocTable>) -> Result<()> {
+        match docs {
+            Some(docs) => { self.f.write_all(&[1])?; docs.write_to(&mut self.f)?; }
+            None => self.f.write_all(&[0])?,
+        }
+        self.f.flush()?;
+        Ok(())
+    }
+}
+
+/// Streaming BWT run-length encoder: (u8 symbol, varint run_len) pairs.
+pub struct RleEncoder<W: Write> { w: W, cur: Option<(u8, u64)>, buf: Vec<u8> }
+
+impl<W: Write> RleEncoder<W> {
+    pub fn new(w: W) -> Self { Self { w, cur: None, buf: Vec::new() } }
+
+    pub fn push(&mut self, ch: u8) -> Result<()> {
+        match &mut self.cur {
+            Some((cur, run)) if *cur == ch && *run < u64::MAX / 2 => *run += 1,
+            _ => {
+                self.flush_run()?;
+                self.cur = Some((ch, 1));
+            }
+        }
+        Ok(())
+    }
+
+    fn flush_run(&mut self) -> Result<()> {
+        if let Some((ch, run)) = self.cur.take() {
+            self.buf.clear();
+            self.buf.push(ch);
+            encode_varint(run, &mut self.buf);
+            self.w.write_all(&self.buf)?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<W> {
+        self.flush_run()?;
+        Ok(self.w)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::suffix_array::build_sa;
+
+    fn fm(s: &[u8]) -> FMIndex {
+        FMIndex::build(s, &build_sa(s), 2)
+    }
+
+    #[test]
+    fn any_byte_including_dollar_and_nul() {
+        let text: &[u8] = b"$5 or $10\0$\0\0 a$b $$\0";
+        let idx = fm(text);
+        for pat in [&b"$"[..], b"$$", b"\0", b"\0\0", b"$1", b"a$b", b" $"] {
+            let mut got = idx.backward_search(pat).map_or(vec![], |r| idx.locate_range(&r, usize::MAX));
+            got.sort_unstable();
+            let naive: Vec<usize> = (0..=text.len().saturating_sub(pat.len())).filter(|&i| text[i..].starts_with(pat)).collect();
+            assert_eq!(got, naive, "{pat:?}");
+        }
+        assert_eq!(idx.extract(0, text.len()).unwrap(), text);
+    }
+
+    #[test]
+    fn approx_hamming_and_edit() {
+        let idx = fm(b"the quick brown fox; the qu1ck brwn fox");
+        let locs = |ms: Vec<ApproxMatch>| {
+            let mut v: Vec<(usize, u32)> =
+                ms.iter().flat_map(|m| idx.locate_range(&m.range, usize::MAX).into_iter().map(move |p| (p, m.cost))).collect();
+            v.sort_unstable();
+            v
+        };
+        let search = |pat: &[u8], mode| idx.approx_search(pat, 1, mode, 100_000).unwrap();
+        assert_eq!(locs(search(b"quick", ApproxMode::Hamming)), vec![(4, 0), (25, 1)]);
+        assert_eq!(locs(search(b"brown", ApproxMode::Hamming)), vec![(10, 0)]);
+        let edit = search(b"brown", ApproxMode::Edit);
+        assert!(edit.iter().any(|m| m.cost == 1 && m.len == 4 && idx.locate_range(&m.range, 9) == vec![31]));
+        assert_eq!(edit[0], ApproxMatch { range: idx.backward_search(b"brown").unwrap(), cost: 0, len: 5 });
+        assert!(idx.approx_search(b"brown", 2, ApproxMode::Edit, 50).is_err());
+        // one hit per place: "rown" inside "brown" and "brwn" alone
+        assert_eq!(idx.approx_locate(b"brown", 1, ApproxMode::Edit, 10, 100_000).unwrap(), vec![(10, 5, 0), (31, 4, 1)]);
+        assert_eq!(idx.approx_locate(b"brown", 1, ApproxMode::Edit, 1, 100_000).unwrap(), vec![(10, 5, 0)]);
+    }
+
+    #[test]
+    fn extract_and_legacy_isa() {
+        let text = b"the quick brown fox jumps over the lazy dog";
+        let idx = fm(text);
+        for (start, len) in [(0, 3), (4, 5), (10, 0), (31, 12), (0, text.len())] {
+            assert_eq!(idx.extract(start, len).unwrap(), &text[start..start + len]);
+        }
+        assert!(idx.extract(40, 4).is_err());
+        assert_eq!(idx.rebuild_isa_samples(), idx.isa_samp);
+    }
+
+    #[test]
+    fn context_around_rows() {
+        let idx = fm(b"the quick brown fox");
+        let r = idx.backward_search(b"brown").unwrap();
+        assert_eq!(idx.bytes_before(r.l, 6), b"quick ");
+        assert_eq!(idx.bytes_after(r.l, 9), b"brown fox");
+        let the = idx.backward_search(b"the").unwrap().l;
+        assert_eq!(idx.bytes_after(the, 30), b"the quick brown fox");
+        assert_eq!(idx.bytes_before(the, 5), b"");
+    }
+
+    #[test]
+    fn save_load_locate() {
+        let s = b"mississippi";
+        let sa = build_sa(s);
+        let path = std::env::temp_dir().join(format!("fm-save-load-{}.fmx", std::process::id()));
+        let path = path.to_str().unwrap();
+        fm(s).save(path).unwrap();
+        let loaded = FMIndex::load(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        // row 0 is the virtual terminator
+        for (r, &p) in sa.iter().enumerate() {
+            assert_eq!(loaded.locate(r as u64 + 1), p, "row {}", r + 1);
+        }
+    }
+}
@@ -1,36 +1,145 @@
-use std::collections::BTreeMap; use crate::util::varint::*;
-#[derive(Default)] pub struct InvBuilder{ map:BTreeMap<String,BTreeMap<u32,Vec<u32>>> }
+use std::collections::BTreeMap; use crate::util::varint::*; use anyhow::*; use serde::{Deserialize, Serialize};
+/// Per term, per doc: (token ordinal, byte start, byte end) of every occurrence.
+type Occurrences=BTreeMap<String,BTreeMap<u32,Vec<(u32,u32,u32)>>>;
+#[derive(Default)] pub struct InvBuilder{ map:Occurrences, n_docs:u32 }
 impl InvBuilder{
-    pub fn add_doc(&mut self,docid:u32,text:&str){ for (tok,pos) in crate::text::tokenize::tokenize(text){ self.map.entry(tok).or_default().entry(docid).or_default().push(pos as u32); } }
-    pub fn finalize(self)->InvertedIndex{ InvertedIndex::from_map(self.map) }
+    /// Positions are token ordinals (0 = first token of the doc); byte spans go to the offsets stream.
+    pub fn add_doc(&mut self,docid:u32,text:&str){ self.n_docs=self.n_docs.max(docid+1); for (ord,(tok,start,end)) in crate::text::tokenize::tokenize_spans(text).into_iter().enumerate(){ self.map.entry(tok).or_default().entry(docid).or_default().push((ord as u32,start as u32,end as u32)); } }
+    /// Also computes collection frequencies.
+    pub fn finalize(self)->InvertedIndex{ InvertedIndex::from_map(self.map,self.n_docs) }
 }
-pub struct InvertedIndex{ pub dict:BTreeMap<String,Vec<u8>> }
+/// `dict`: term -> varint postings (doc delta, freq, ordinal deltas).
+/// `offsets`: term -> varint byte spans (start delta, len) per occurrence, in
+/// the same doc/occurrence order as `dict`; used for highlighting only.
+/// `cf`: term -> occurrences in the whole collection (for language models).
+/// `n_docs`: documents in the collection (one past the highest doc id added).
+#[derive(Default)] pub struct InvertedIndex{ pub dict:BTreeMap<String,Vec<u8>>, pub offsets:BTreeMap<String,Vec<u8>>, pub cf:BTreeMap<String,u64>, pub n_docs:u32 }
+
+/// On-disk layout (JSON). Version 1 files were a bare `dict` whose positions
+/// were token end byte offsets; version 2 had no `cf` and versions before 5
+/// no `n_docs`. `load` migrates all of them. Versions 4 and 5 also stored
+/// SMART doc norms; those are now computed on demand (see `doc_norms`).
+const FORMAT_VERSION:u32=5;
+#[derive(Serialize,Deserialize)] struct InvFile{ version:u32, dict:BTreeMap<String,Vec<u8>>, offsets:BTreeMap<String,Vec<u8>>, #[serde(default)] cf:BTreeMap<String,u64>, #[serde(default,skip_serializing)] doc_norms:BTreeMap<String,Vec<f32>>, #[serde(default)] n_docs:Option<u32> }
+#[derive(Deserialize)] #[serde(untagged)] enum AnyInvFile{ Current(InvFile), Legacy(BTreeMap<String,Vec<u8>>) }
+
 impl InvertedIndex{
-    fn from_map(map:BTreeMap<String,BTreeMap<u32,Vec<u32>>>)->Self{
-        let mut dict=BTreeMap::new();
+    fn from_map(map:Occurrences,n_docs:u32)->Self{
+        let mut dict=BTreeMap::new(); let mut offsets=BTreeMap::new(); let mut cf=BTreeMap::new();
         for (term,docs) in map{
-            let mut buf=Vec::new(); let mut last=0u64;
-            for (docid,mut pos) in docs{
-                pos.sort_unstable();
+            cf.insert(term.clone(),docs.values().map(|occ| occ.len() as u64).sum());
+            let mut buf=Vec::new(); let mut obuf=Vec::new(); let mut last=0u64;
+            for (docid,mut occ) in docs{
+                occ.sort_unstable();
                 let d=(docid as u64)-last; last=docid as u64; encode_varint(d,&mut buf);
-                encode_varint(pos.len() as u64,&mut buf);
-                let mut prev=0u64; for p in pos{ let delta=(p as u64)-prev; prev=p as u64; encode_varint(delta,&mut buf); }
+                encode_varint(occ.len() as u64,&mut buf);
+                let mut prev=0u64; for &(p,_,_) in &occ{ let delta=(p as u64)-prev; prev=p as u64; encode_varint(delta,&mut buf); }
+                let mut prev=0u64; for &(_,s,e) in &occ{ encode_varint(s as u64-prev,&mut obuf); encode_varint((e-s) as u64,&mut obuf); prev=s as u64; }
             }
+            offsets.insert(term.clone(),obuf);
             dict.insert(term,buf);
         }
-        Self{dict}
+        Self{dict,offsets,cf,n_docs}
     }
+    /// `(doc, tf)` postings of every term, in `dict` order.
+    fn term_freqs(&self)->Result<Vec<Vec<(u32,u32)>>>{
+        self.dict.values().map(|b| Ok(decode_postings(b)?.into_iter().map(|(d,p)| (d,p.len() as u32)).collect())).collect()
+    }
+    /// Vector norm per doc id under SMART doc weighting `w`, for a collection
+    /// of `n_docs` documents (idf weights depend on it).
+    pub fn doc_norms(&self,w:&crate::rank::vsm::Smart,n_docs:u32)->Result<Vec<f32>>{
+        let tfs=self.term_freqs()?;
+        Ok(crate::rank::vsm::doc_norms(w,n_docs,tfs.iter().map(Vec::as_slice)))
+    }
+    /// Occurrences of `term` in the collection (0 if absent).
+    pub fn cf(&self,term:&str)->u64{ self.cf.get(term).copied().unwrap_or(0) }
     pub fn postings(&self,term:&str)->Vec<(u32,Vec<u32>)>{
         let Some(bytes)=self.dict.get(term) else { return vec![] };
-        let mut i=0usize; let mut res=Vec::new(); let mut last=0u64;
-        while i<bytes.len(){
-            let (d,j1)=decode_varint(bytes,i).unwrap(); i=j1; let docid=(last+d) as u32; last+=d;
-            let (freq,j2)=decode_varint(bytes,i).unwrap(); i=j2;
-            let mut pos=Vec::with_capacity(freq as usize); let mut prev=0u64;
-            for _ in 0..freq{ let (dv,j3)=decode_varint(bytes,i).unwrap(); i=j3; prev+=dv; pos.push(prev as u32); }
-            res.push((docid,pos));
+        decode_postings(bytes).unwrap()
+    }
+    /// Byte spans (start, end) of each occurrence, parallel to `postings(term)`.
+    pub fn offsets(&self,term:&str)->Vec<(u32,Vec<(u32,u32)>)>{
+        let Some(bytes)=self.offsets.get(term) else { return vec![] };
+        let mut i=0usize; let mut res=Vec::new();
+        for (docid,pos) in self.postings(term){
+            let mut spans=Vec::with_capacity(pos.len()); let mut prev=0u64;
+            for _ in 0..pos.len(){ let (ds,j1)=decode_varint(bytes,i).unwrap(); let (len,j2)=decode_varint(bytes,j1).unwrap(); i=j2; prev+=ds; spans.push((prev as u32,(prev+len) as u32)); }
+            res.push((docid,spans));
         }
         res
     }
+
+    pub fn save(&self,path:&str)->Result<()>{
+        let file=InvFile{ version:FORMAT_VERSION, dict:self.dict.clone(), offsets:self.offsets.clone(), cf:self.cf.clone(), doc_norms:BTreeMap::new(), n_docs:Some(self.n_docs) };
+        std::fs::write(path,serde_json::to_vec(&file)?)?; Ok(())
+    }
+    /// Load an index, migrating version-1 files in memory (see `from_legacy`)
+    /// and recomputing `cf` missing from version 2. Before version 5 `n_docs`
+    /// is the length of the stored norms (version 4), else one past the
+    /// highest doc id with postings.
+    pub fn load(path:&str)->Result<Self>{
+        let buf=std::fs::read(path)?;
+        match serde_json::from_slice::<AnyInvFile>(&buf).with_context(|| format!("bad inverted index file {path}"))?{
+            AnyInvFile::Current(f)=>{
+                ensure!((2..=FORMAT_VERSION).contains(&f.version),"unsupported inverted index version {}",f.version);
+                let mut inv=Self{dict:f.dict,offsets:f.offsets,cf:f.cf,n_docs:f.n_docs.unwrap_or(0)};
+                if f.version<FORMAT_VERSION{
+                    let tfs=inv.term_freqs()?;
+                    let stored=f.doc_norms.values().next().map(|v| v.len() as u32);
+                    inv.n_docs=stored.unwrap_or_else(|| tfs.iter().flatten().map(|&(d,_)| d+1).max().unwrap_or(0));
+                    if f.version==2{ inv.cf=inv.dict.keys().cloned().zip(tfs.iter().map(|ps| ps.iter().map(|&(_,tf)| tf as u64).sum())).collect(); }
+                }
+                Ok(inv)
+            }
+            AnyInvFile::Legacy(dict)=>Self::from_legacy(&dict),
+        }
+    }
+    /// Rebuild from a version-1 dict. Its positions are token end offsets, so
+    /// sorting a doc's ends gives token ordinals, and since the tokenizer only
+    /// ASCII-lowercases, start = end - term length in bytes.
+    pub fn from_legacy(dict:&BTreeMap<String,Vec<u8>>)->Result<Self>{
+        let mut per_doc:BTreeMap<u32,Vec<(u32,&str)>>=BTreeMap::new();
+        for (term,bytes) in dict{ for (docid,ends) in decode_postings(bytes)?{ per_doc.entry(docid).or_default().extend(ends.into_iter().map(|e| (e,term.as_str()))); } }
+        let n_docs=per_doc.keys().next_back().map_or(0,|d| d+1);
+        let mut map:Occurrences=BTreeMap::new();
+        for (docid,mut toks) in per_doc{
+            toks.sort_unstable();
+            for (ord,(end,term)) in toks.into_iter().enumerate(){
+                let start=end.checked_sub(term.len() as u32).context("legacy position before term start")?;
+                map.entry(term.to_string()).or_default().entry(docid).or_default().push((ord as u32,start,end));
+            }
+        }
+        Ok(Self::from_map(map,n_docs))
+    }
+}
+fn decode_postings(bytes:&[u8])->Result<Vec<(u32,Vec<u32>)>>{
+    let mut i=0usize; let mut res=Vec::new(); let mut last=0u64;
+    while i<bytes.len(){
+        let (d,j1)=decode_varint(bytes,i)?; i=j1; let docid=(last+d) as u32; last+=d;
+        let (freq,j2)=decode_varint(bytes,i)?; i=j2;
+        let mut pos=Vec::with_capacity(freq as usize); let mut prev=0u64;
+        for _ in 0..freq{ let (dv,j3)=decode_varint(bytes,i)?; i=j3; prev+=dv; pos.push(prev as u32); }
+        res.push((docid,pos));
+    }
+    Ok(res)
+}
+#[cfg(test)] mod tests{ use super::*;
+    #[test] fn build_and_read(){ let mut b=InvBuilder::default(); b.add_doc(1,"the cat sat on the mat"); b.add_doc(2,"the cat ate the rat"); let inv=b.finalize(); assert_eq!(inv.postings("cat").len(),2); assert_eq!(inv.postings("the"),vec![(1,vec![0,4]),(2,vec![0,3])]); assert_eq!(inv.offsets("cat"),vec![(1,vec![(4,7)]),(2,vec![(4,7)])]); assert_eq!((inv.cf("the"),inv.cf("rat"),inv.cf("dog")),(4,1,0));
+        let nnc=crate::rank::vsm::Smart{tf:b'n',df:b'n',norm:b'c'};
+        assert_eq!(inv.doc_norms(&nnc,3).unwrap(),vec![0.0,(4f32+1.+1.+1.+1.).sqrt(),(4f32+1.+1.+1.).sqrt()]);
+        // version-2 files have no cf or n_docs; they are recomputed on load
+        let dir=tempfile::tempdir().unwrap(); let path=dir.path().join("v2.inv"); let path=path.to_str().unwrap();
+        std::fs::write(path,serde_json::to_vec(&serde_json::json!({"version":2,"dict":inv.dict,"offsets":inv.offsets})).unwrap()).unwrap();
+        let back=InvertedIndex::load(path).unwrap(); assert_eq!((back.cf,back.n_docs),(inv.cf.clone(),3));
+        // n_docs counts trailing docs without postings and survives a round trip
+        let mut b=InvBuilder::default(); b.add_doc(0,"cat"); b.add_doc(2,""); let inv=b.finalize(); assert_eq!(inv.n_docs,3);
+        let path=dir.path().join("v5.inv"); let path=path.to_str().unwrap(); inv.save(path).unwrap(); assert_eq!(InvertedIndex::load(path).unwrap().n_docs,3); }
+    #[test] fn migrate_v1(){
+        // v1 stored token end offsets: "Neural networks rock" -> neural@6 networks@15 rock@20
+        let mut legacy=BTreeMap::new();
+        for (t,end) in [("neural",6u64),("networks",15),("rock",20)]{ let mut buf=Vec::new(); for v in [0,1,end]{ encode_varint(v,&mut buf); } legacy.insert(t.to_string(),buf); }
+        let inv=InvertedIndex::from_legacy(&legacy).unwrap();
+        assert_eq!(inv.postings("networks"),vec![(0,vec![1])]);
+        assert_eq!(inv.offsets("networks"),vec![(0,vec![(7,15)])]);
+    }
 }
-#[cfg(test)] mod tests{ use super::*; #[test] fn build_and_read(){ let mut b=InvBuilder::default(); b.add_doc(1,"the cat sat on the mat"); b.add_doc(2,"the cat ate the rat"); let inv=b.finalize(); assert_eq!(inv.postings("cat").len(),2); } }
@@ -1,5 +1,18 @@
-pub fn build_sa(s:&[u8])->Vec<usize>{
-    let n=s.len(); let mut sa:(Vec<usize>)=(0..n).collect();
+/// Suffix array of `s` (suffixes compared as plain byte strings, a shorter
+/// suffix before any extension of it). Linear-time SA-IS on the byte
+/// alphabet.
+pub fn build_sa(s:&[u8])->Vec<usize>{ sais(s, 256) }
+
+/// Suffix array of a string over the integer alphabet `0..alphabet`, same
+/// order as `build_sa`.
+pub fn build_sa_ints(s:&[u32], alphabet:usize)->Vec<usize>{
+    debug_assert!(s.iter().all(|&c| (c as usize) < alphabet));
+    sais(s, alphabet)
+}
+
+/// Prefix doubling, O(n log² n). Kept as the reference `build_sa` is checked against.
+pub fn build_sa_doubling(s:&[u8])->Vec<usize>{
+    let n=s.len(); let mut sa:Vec<usize>=(0..n).collect();
     let mut rank:Vec<i32>=s.iter().map(|&c| c as i32).collect(); let mut tmp=vec![0i32;n]; let mut k=1usize;
     while k<n{
         sa.sort_unstable_by(|&i,&j|{ (rank[i], if i+k<n{rank[i+k]} else {-1}).cmp(&(rank[j], if j+k<n{rank[j+k]} else {-1})) });
@@ -10,9 +23,134 @@ pub fn build_sa(s:&[u8])->Vec<usize>{
             let cur =(rank[b], if b+k<n{rank[b+k]} else {-1});
             tmp[b]=tmp[a]+ if cur>prev {1} else {0};
         }
-        for i in 0..n { rank[i]=tmp[i]; }
+        rank.copy_from_slice(&tmp);
         if rank[sa[n-1]] as usize == n-1 { break; }
         k<<=1;
     } sa
 }
-#[cfg(test)] mod tests{ use super::*; #[test] fn sa_basic(){ let s=b"banana$"; assert_eq!(build_sa(s),vec![6,5,3,1,0,4,2]); } }
+
+// ---- SA-IS (Nong, Zhang & Chan), with a virtual sentinel past the end ----
+
+const EMPTY: usize = usize::MAX;
+
+/// Symbols SA-IS can bucket: bytes, caller integers, and reduced-string names.
+trait Symbol: Copy + Ord { fn idx(self) -> usize; }
+impl Symbol for u8 { #[inline] fn idx(self) -> usize { self as usize } }
+impl Symbol for u32 { #[inline] fn idx(self) -> usize { self as usize } }
+impl Symbol for usize { #[inline] fn idx(self) -> usize { self } }
+
+fn sais<T: Symbol>(s: &[T], k: usize) -> Vec<usize> {
+    let n = s.len();
+    if n == 0 { return Vec::new(); }
+    if n == 1 { return vec![0]; }
+
+    // S-type (true) / L-type (false); the last suffix is L against the sentinel
+    let mut stype = vec![false; n];
+    for i in (0..n - 1).rev() {
+        stype[i] = s[i] < s[i + 1] || (s[i] == s[i + 1] && stype[i + 1]);
+    }
+    let is_lms = |i: usize| i > 0 && i < n && stype[i] && !stype[i - 1];
+
+    let mut counts = vec![0usize; k];
+    for &c in s { counts[c.idx()] += 1; }
+    let heads = |out: &mut Vec<usize>| { out.clear(); let mut acc = 0; for &c in &counts { out.push(acc); acc += c; } };
+    let tails = |out: &mut Vec<usize>| { out.clear(); let mut acc = 0; for &c in &counts { acc += c; out.push(acc); } };
+    let mut bkt = Vec::with_capacity(k);
+
+    let mut sa = vec![EMPTY; n];
+    let induce = |sa: &mut [usize], bkt: &mut Vec<usize>| {
+        // L-types left to right; suffix n-1 is induced by the sentinel
+        heads(bkt);
+        let c = s[n - 1].idx();
+        sa[bkt[c]] = n - 1;
+        bkt[c] += 1;
+        for i in 0..n {
+            let j = sa[i];
+            if j != EMPTY && j > 0 && !stype[j - 1] {
+                let c = s[j - 1].idx();
+                sa[bkt[c]] = j - 1;
+                bkt[c] += 1;
+            }
+        }
+        // S-types right to left
+        tails(bkt);
+        for i in (0..n).rev() {
+            let j = sa[i];
+            if j != EMPTY && j > 0 && stype[j - 1] {
+                let c = s[j - 1].idx();
+                bkt[c] -= 1;
+                sa[bkt[c]] = j - 1;
+            }
+        }
+    };
+
+    // 1) sort LMS substrings: seed LMS positions at bucket tails and induce
+    tails(&mut bkt);
+    for i in (1..n).rev() {
+        if is_lms(i) {
+            let c = s[i].idx();
+            bkt[c] -= 1;
+            sa[bkt[c]] = i;
+        }
+    }
+    induce(&mut sa, &mut bkt);
+
+    // 2) name LMS substrings in sorted order; equal substrings share a name
+    let sorted_lms: Vec<usize> = sa.iter().copied().filter(|&i| is_lms(i)).collect();
+    let lms_equal = |a: usize, b: usize| {
+        for d in 0.. {
+            // only one suffix can reach the sentinel at a given d
+            if a + d == n || b + d == n { return false; }
+            if s[a + d] != s[b + d] || stype[a + d] != stype[b + d] { return false; }
+            if d > 0 {
+                match (is_lms(a + d), is_lms(b + d)) {
+                    (true, true) => return true,
+                    (false, false) => {}
+                    _ => return false,
+                }
+            }
+        }
+        unreachable!()
+    };
+    let mut names = vec![EMPTY; n];
+    let mut name = 0usize;
+    for (x, &p) in sorted_lms.iter().enumerate() {
+        if x > 0 && !lms_equal(sorted_lms[x - 1], p) { name += 1; }
+        names[p] = name;
+    }
+    let n_names = if sorted_lms.is_empty() { 0 } else { name + 1 };
+
+    // 3) order of LMS suffixes: directly if names are unique, else recurse
+    let lms_pos: Vec<usize> = (1..n).filter(|&i| is_lms(i)).collect();
+    let lms_order: Vec<usize> = if n_names == lms_pos.len() {
+        sorted_lms
+    } else {
+        let reduced: Vec<usize> = lms_pos.iter().map(|&p| names[p]).collect();
+        drop(names);
+        sais(&reduced, n_names).into_iter().map(|r| lms_pos[r]).collect()
+    };
+
+    // 4) seed sorted LMS suffixes at bucket tails (last first) and induce
+    sa.fill(EMPTY);
+    tails(&mut bkt);
+    for &p in lms_order.iter().rev() {
+        let c = s[p].idx();
+        bkt[c] -= 1;
+        sa[bkt[c]] = p;
+    }
+    induce(&mut sa, &mut bkt);
+    sa
+}
+
+#[cfg(test)] mod tests{ use super::*; #[test] fn sa_basic(){ let s=b"banana$"; assert_eq!(build_sa(s),vec![6,5,3,1,0,4,2]); }
+    #[test] fn sais_matches_doubling(){
+        let mut texts: Vec<Vec<u8>> = vec![b"".to_vec(), b"a".to_vec(), b"aaaaaaaa".to_vec(), b"abababab$".to_vec(), b"mississippi".to_vec(), b"\0\xff\0\xff\0".to_vec()];
+        let mut x = 12345u32;
+        for len in [50, 333, 2000] { for sigma in [2u32, 4, 256] {
+            texts.push((0..len).map(|_| { x = x.wrapping_mul(1103515245).wrapping_add(12345); ((x >> 16) % sigma) as u8 }).collect());
+        } }
+        texts.push(b"abcabcabcabxabcabcabcabx".repeat(20));
+        for t in &texts { assert_eq!(build_sa(t), build_sa_doubling(t), "{:?}", String::from_utf8_lossy(t)); }
+        let ints: Vec<u32> = b"mississippi".iter().map(|&c| c as u32 * 1000).collect();
+        assert_eq!(build_sa_ints(&ints, 200_000), build_sa_doubling(b"mississippi"));
+    } }
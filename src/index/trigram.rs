@@ -0,0 +1,151 @@
+use crate::index::doctable::{CollectionWriter, DocTable};
+use crate::util::varint::{decode_varint, encode_varint};
+use anyhow::*;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+/// A byte trigram packed as `b0 << 16 | b1 << 8 | b2`.
+pub type Trigram = u32;
+
+pub fn trigram(t: &[u8]) -> Trigram { (t[0] as u32) << 16 | (t[1] as u32) << 8 | t[2] as u32 }
+
+/// Builds a [`TrigramIndex`] one document at a time.
+pub struct TrigramBuilder {
+    coll: CollectionWriter<Vec<u8>>,
+    map: BTreeMap<Trigram, Vec<u32>>,
+}
+
+impl Default for TrigramBuilder {
+    fn default() -> Self { Self { coll: CollectionWriter::new(Vec::new()), map: BTreeMap::new() } }
+}
+
+impl TrigramBuilder {
+    /// Add a document, returning its id. Trigrams never span documents.
+    pub fn add(&mut self, name: &str, bytes: &[u8]) -> Result<u32> {
+        let doc = self.coll.add(name, bytes)?;
+        let mut tris: Vec<Trigram> = bytes.windows(3).map(trigram).collect();
+        tris.sort_unstable();
+        tris.dedup();
+        for t in tris { self.map.entry(t).or_default().push(doc); }
+        Ok(doc)
+    }
+
+    pub fn finalize(self) -> TrigramIndex {
+        let (text, docs) = self.coll.finish();
+        let postings = self.map.into_iter().map(|(t, ids)| {
+            let mut buf = Vec::new();
+            let mut last = 0u64;
+            for d in ids { encode_varint(d as u64 - last, &mut buf); last = d as u64; }
+            (t, buf)
+        }).collect();
+        TrigramIndex { postings, docs, text }
+    }
+}
+
+/// Per-document trigram postings (varint doc id deltas, as in
+/// `InvertedIndex::dict`) over a stored collection, so candidate documents
+/// can be confirmed against their text.
+pub struct TrigramIndex {
+    postings: BTreeMap<Trigram, Vec<u8>>,
+    docs: DocTable,
+    text: Vec<u8>,
+}
+
+impl TrigramIndex {
+    pub fn docs(&self) -> &DocTable { &self.docs }
+    pub fn n_trigrams(&self) -> usize { self.postings.len() }
+
+    pub fn doc_text(&self, doc: u32) -> Option<&[u8]> {
+        let (start, len) = self.docs.span(doc)?;
+        self.text.get(start..start + len)
+    }
+
+    /// Sorted ids of the documents containing trigram `t`.
+    pub fn postings(&self, t: Trigram) -> Vec<u32> {
+        let Some(bytes) = self.postings.get(&t) else { return vec![] };
+        let mut out = Vec::new();
+        let (mut i, mut last) = (0usize, 0u64);
+        while i < bytes.len() {
+            let (d, next) = decode_varint(bytes, i).expect("corrupt trigram postings");
+            i = next;
+            last += d;
+            out.push(last as u32);
+        }
+        out
+    }
+
+    /// Save with: magic "TRI1", doc table, u64 len + text, u64 trigram count,
+    /// then per trigram u32 key, u64 len + postings.
+    pub fn save(&self, path: &str) -> Result<()> {
+        let mut f = BufWriter::new(File::create(path)?);
+        f.write_all(b"TRI1")?;
+        self.docs.write_to(&mut f)?;
+        f.write_all(&(self.text.len() as u64).to_le_bytes())?;
+        f.write_all(&self.text)?;
+        f.write_all(&(self.postings.len() as u64).to_le_bytes())?;
+        for (t, bytes) in &self.postings {
+            f.write_all(&t.to_le_bytes())?;
+            f.write_all(&(bytes.len() as u64).to_le_bytes())?;
+            f.write_all(bytes)?;
+        }
+        f.flush()?;
+        Ok(())
+    }
+
+    pub fn load(path: &str) -> Result<Self> {
+        let mut f = BufReader::new(File::open(path).with_context(|| format!("opening {path}"))?);
+        let mut magic = [0u8; 4];
+        f.read_exact(&mut magic)?;
+        ensure!(&magic == b"TRI1", "bad trigram index file (magic)");
+        let docs = DocTable::read_from(&mut f)?;
+        let text = read_section(&mut f)?;
+        let mut postings = BTreeMap::new();
+        for _ in 0..read_u64(&mut f)? {
+            let mut b4 = [0u8; 4];
+            f.read_exact(&mut b4)?;
+            postings.insert(u32::from_le_bytes(b4), read_section(&mut f)?);
+        }
+        ensure!(docs.span(docs.len().saturating_sub(1) as u32).is_none_or(|(s, l)| s + l <= text.len()),
+            "doc table does not match the stored text");
+        Ok(Self { postings, docs, text })
+    }
+}
+
+fn read_u64(r: &mut impl Read) -> Result<u64> {
+    let mut b8 = [0u8; 8];
+    r.read_exact(&mut b8)?;
+    Ok(u64::from_le_bytes(b8))
+}
+
+fn read_section(r: &mut impl Read) -> Result<Vec<u8>> {
+    let mut buf = vec![0u8; read_u64(r)? as usize];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn postings_and_roundtrip() {
+        let mut b = TrigramBuilder::default();
+        b.add("a", b"hello world").unwrap();
+        b.add("b", b"yellow").unwrap();
+        b.add("c", b"lo").unwrap();
+        let idx = b.finalize();
+        assert_eq!(idx.postings(trigram(b"llo")), vec![0, 1]);
+        assert_eq!(idx.postings(trigram(b"wor")), vec![0]);
+        assert!(idx.postings(trigram(b"d\ny")).is_empty()); // no trigram crosses docs
+        assert_eq!(idx.doc_text(1), Some(&b"yellow"[..]));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("t.tri");
+        idx.save(path.to_str().unwrap()).unwrap();
+        let back = TrigramIndex::load(path.to_str().unwrap()).unwrap();
+        assert_eq!(back.postings(trigram(b"llo")), vec![0, 1]);
+        assert_eq!((back.docs().label(2), back.doc_text(2)), ("c".to_string(), Some(&b"lo"[..])));
+        assert_eq!(back.n_trigrams(), idx.n_trigrams());
+    }
+}
@@ -7,6 +7,7 @@ pub mod util {
     pub mod timer;
     pub mod bitvec;
     pub mod rrr;
+    pub mod wavelet;
 }
 
 // --- Core text processing ---
@@ -22,6 +23,10 @@ pub mod index {
     pub mod bwt;
     pub mod lcp;
     pub mod suffix_array;
+    pub mod external;
+    pub mod doctable;
+    pub mod code;
+    pub mod trigram;
 }
 
 // --- Query logic ---
@@ -30,6 +35,12 @@ pub mod query {
     pub mod phrase;
     pub mod substring;
     pub mod engine;
+    pub mod wildcard;
+    pub mod fuzzy;
+    pub mod suggest;
+    pub mod fm_regex;
+    pub mod code;
+    pub mod trigram;
 }
 
 // --- Adapter layer ---
@@ -39,7 +50,12 @@ pub mod adapter {
 
 // --- Ranking ---
 pub mod rank {
+    pub mod similarity;
     pub mod bm25;
-pub mod rank;
+    pub mod dfr;
+    pub mod lm;
+    pub mod vsm;
+    #[allow(clippy::module_inception)]
+    pub mod rank;
 }
 
@@ -1,11 +1,8 @@
 use clap::{Parser, Subcommand};
 use anyhow::Result;
 
-mod util { pub mod varint; pub mod bitvec; pub mod mmap; pub mod timer;  pub mod rrr; }
-mod text { pub mod tokenize; pub mod corpus; }
-mod index { pub mod inverted; pub mod suffix_array; pub mod lcp; pub mod bwt; pub mod fmindex; }
-mod rank { pub mod bm25; }
-mod query { pub mod boolean; pub mod phrase; pub mod substring; pub mod engine; }
+use searchkit::{index, query};
+use searchkit::query::substring::DocTable;
 
 #[derive(Parser)]
 #[command(name = "searchkit")]
@@ -19,21 +16,176 @@ enum Cmd {
     /// Build inverted index from a single text file (each line = one doc)
     BuildInv { corpus: String, out: String },
 
-    /// Build and save FM-index from a text file (appends '$' if missing)
-    BuildFm { text: String, out: String, sa_sample: usize },
+    /// Build and save FM-index from a file (any bytes; one doc per line)
+    BuildFm {
+        text: String,
+        out: String,
+        sa_sample: usize,
+        /// Build on disk, keeping working memory (not counting the memory-mapped
+        /// text) within this many MiB (for texts larger than RAM)
+        #[arg(long)]
+        mem_budget_mb: Option<usize>,
+    },
 
-    /// Query term/phrase via inverted index
-    QueryInv { index: String, q: String, k: usize },
+    /// Build one FM-index over many documents: whole files, or JSONL records
+    /// (`--jsonl`). Matches never cross documents, and results print as
+    /// `doc_name:offset`.
+    BuildFmCollection {
+        out: String,
+        sa_sample: usize,
+        #[arg(required = true)]
+        inputs: Vec<String>,
+        /// Inputs are JSONL, one document per record
+        #[arg(long)]
+        jsonl: bool,
+        /// Record field holding the document text
+        #[arg(long, default_value = "text")]
+        text_field: String,
+        /// Record field naming the document (default: file:line)
+        #[arg(long, default_value = "id")]
+        name_field: String,
+        /// As for build-fm
+        #[arg(long)]
+        mem_budget_mb: Option<usize>,
+    },
 
-    /// Substring query using a saved FM-index file
-    Substr { fm: String, pat: String, max: usize },
+    /// Index a source tree (honouring .gitignore, skipping binary files) into
+    /// an FM-index whose doc table keeps each file's lines for `code-search`
+    CodeIndex {
+        dir: String,
+        out: String,
+        #[arg(long, default_value_t = 32)]
+        sa_sample: usize,
+        /// As for build-fm
+        #[arg(long)]
+        mem_budget_mb: Option<usize>,
+    },
+
+    /// Search a code index, printing `path:line:col: text` per match
+    CodeSearch {
+        index: String,
+        pat: String,
+        #[arg(long, default_value_t = 100)]
+        max: usize,
+        /// Treat PAT as a regex (evaluated on the FM-index)
+        #[arg(long)]
+        regex: bool,
+    },
+
+    /// Build a trigram index for regex search (an alternative to the FM-index):
+    /// each input file is a document, directories are walked as for code-index
+    BuildTrigram {
+        out: String,
+        #[arg(required = true)]
+        inputs: Vec<String>,
+    },
+
+    /// Regex search on a trigram index: candidate documents come from the
+    /// regex's trigrams and are confirmed against the stored text
+    RegexTrigram {
+        index: String,
+        pat: String,
+        #[arg(long, default_value_t = 100)]
+        max: usize,
+    },
+
+    /// Rewrite an older inverted index file in the current format
+    MigrateInv { index: String, out: String },
+
+    /// Boolean query via inverted index, e.g. `(neural OR network) AND NOT cats`
+    QueryInv {
+        index: String,
+        q: String,
+        k: usize,
+        /// FM-index built from the same corpus, enables `sub:` terms
+        #[arg(long)]
+        fm: Option<String>,
+        /// Max dictionary terms one wildcard (`neur*`, `n?t`) may expand to
+        #[arg(long, default_value_t = query::wildcard::DEFAULT_MAX_EXPANSIONS)]
+        max_expansions: usize,
+        /// Ranking function: bm25, bm25+, bm25l, lm-dirichlet, lm-jm, vsm,
+        /// pl2, inl2 or dph
+        #[arg(long, default_value = "bm25")]
+        similarity: String,
+        #[command(flatten)]
+        params: SimArgs,
+    },
+
+    /// Substring query using a saved FM-index file (reported per doc when the
+    /// index has a doc table)
+    Substr {
+        fm: String,
+        pat: String,
+        max: usize,
+        /// Allow up to this many mismatches (approximate search)
+        #[arg(long, default_value_t = 0)]
+        k: u32,
+        /// With --k, also allow insertions and deletions (edit distance)
+        #[arg(long)]
+        edits: bool,
+        /// With --k, give up after visiting this many search nodes
+        #[arg(long, default_value_t = query::fm_regex::DEFAULT_MAX_NODES)]
+        max_nodes: usize,
+    },
+
+    /// Regex search on a saved FM-index, without decompressing the text
+    Regex {
+        fm: String,
+        pat: String,
+        max: usize,
+        /// Give up after visiting this many (state, substring) nodes
+        #[arg(long, default_value_t = query::fm_regex::DEFAULT_MAX_NODES)]
+        max_nodes: usize,
+    },
+
+    /// Print text bytes decoded from a saved FM-index (no original file needed)
+    Extract {
+        fm: String,
+        start: usize,
+        len: Option<usize>,
+        /// Treat START as a doc id from the index's doc table and print that doc
+        #[arg(long)]
+        doc: bool,
+    },
+}
+
+/// Similarity parameters; unset ones use the model's defaults.
+#[derive(clap::Args)]
+struct SimArgs {
+    /// BM25 term frequency saturation (default 1.2)
+    #[arg(long)]
+    k1: Option<f32>,
+    /// BM25 length normalization, 0 = none, 1 = full (default 0.75)
+    #[arg(long)]
+    b: Option<f32>,
+    /// BM25+ / BM25L tf shift (default 1.0 / 0.5)
+    #[arg(long)]
+    delta: Option<f32>,
+    /// lm-dirichlet prior (default 2000)
+    #[arg(long)]
+    mu: Option<f32>,
+    /// lm-jm collection weight in (0, 1) (default 0.1)
+    #[arg(long)]
+    lambda: Option<f32>,
+    /// vsm SMART weighting, document.query (default lnc.ltc)
+    #[arg(long)]
+    smart: Option<String>,
+    /// pl2 / inl2 length normalization (default 1.0)
+    #[arg(long)]
+    c: Option<f32>,
+}
+
+impl From<SimArgs> for searchkit::rank::similarity::SimilarityParams {
+    fn from(a: SimArgs) -> Self {
+        Self { k1: a.k1, b: a.b, delta: a.delta, mu: a.mu, lambda: a.lambda, smart: a.smart, c: a.c }
+    }
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
     match cli.cmd {
         Cmd::BuildInv { corpus, out } => {
-            use std::{fs::File, io::{BufRead, BufReader, Write}};
+            use std::{fs::File, io::{BufRead, BufReader}};
             let f = File::open(&corpus)?;
             let mut b = index::inverted::InvBuilder::default();
             let mut docid: u32 = 0;
@@ -43,62 +195,201 @@ fn main() -> Result<()> {
                 docid += 1;
             }
             let inv = b.finalize();
-            let mut w = File::create(&out)?;
-            let json = serde_json::to_vec(&inv.dict)?;
-            w.write_all(&json)?;
+            inv.save(&out)?;
             eprintln!("Inverted index built: {} docs -> {}", docid, out);
         }
 
-        Cmd::QueryInv { index, q, k } => {
-            use std::fs::File;
-            use std::io::Read;
-            use std::collections::BTreeMap;
-            let mut buf = Vec::new();
-            File::open(&index)?.read_to_end(&mut buf)?;
-            let dict: BTreeMap<String, Vec<u8>> = serde_json::from_slice(&buf)?;
-            let inv = index::inverted::InvertedIndex { dict };
-
-            let terms: Vec<_> = q.split_whitespace().collect();
-            let hits = if terms.len() == 1 {
-                inv.postings(terms[0]).into_iter().map(|(d,_)| d).collect::<Vec<_>>()
+        Cmd::MigrateInv { index, out } => {
+            index::inverted::InvertedIndex::load(&index)?.save(&out)?;
+            eprintln!("Inverted index migrated: {} -> {}", index, out);
+        }
+
+        Cmd::QueryInv { index, q, k, fm, max_expansions, similarity, params } => {
+            let inv = index::inverted::InvertedIndex::load(&index)?;
+            let sim = searchkit::rank::similarity::similarity_by_name(&similarity, &params.into())?;
+            let mut engine = query::engine::QueryEngine::new(inv)
+                .with_max_expansions(max_expansions)
+                .with_similarity(sim)?;
+            if let Some(path) = fm {
+                let fm = index::fmindex::FMIndex::load(&path)?;
+                let docs = fm.docs().cloned().ok_or_else(|| anyhow::anyhow!("{path} has no doc table"))?;
+                engine = engine.with_fm(fm, docs);
+            }
+            let hits = engine.search(&query::boolean::parse(&q)?, k)?;
+            println!("hits[{}]:", hits.len());
+            for h in hits {
+                println!("  doc={} score={:.4}", h.doc, h.score);
+            }
+        }
+
+        Cmd::BuildFm { text, out, sa_sample, mem_budget_mb } => {
+            index::fmindex::FMIndex::check_text_len(searchkit::util::mmap::file_len(&text)? + 1)?;
+            let file = std::fs::File::open(&text)?;
+            let docs = DocTable::from_lines(&searchkit::util::mmap::mmap_file(&file)?);
+            save_fm(&file, docs, &out, sa_sample, mem_budget_mb)?;
+        }
+
+        Cmd::BuildFmCollection { out, sa_sample, inputs, jsonl, text_field, name_field, mem_budget_mb } => {
+            use index::doctable::CollectionWriter;
+            let mut coll = CollectionWriter::new(std::io::BufWriter::new(tempfile::tempfile()?));
+            for input in &inputs {
+                if !jsonl {
+                    coll.add(input, &std::fs::read(input)?)?;
+                    continue;
+                }
+                for (i, line) in std::fs::read_to_string(input)?.lines().enumerate() {
+                    if line.trim().is_empty() { continue; }
+                    let rec: serde_json::Value = serde_json::from_str(line)
+                        .map_err(|e| anyhow::anyhow!("{input}:{}: {e}", i + 1))?;
+                    let Some(body) = rec.get(&text_field).and_then(|v| v.as_str()) else {
+                        anyhow::bail!("{input}:{}: no string field {text_field:?}", i + 1);
+                    };
+                    let name = match rec.get(&name_field) {
+                        Some(serde_json::Value::String(s)) => s.clone(),
+                        Some(v) if !v.is_null() => v.to_string(),
+                        _ => format!("{input}:{}", i + 1),
+                    };
+                    coll.add(&name, body.as_bytes())?;
+                }
+            }
+            let (text, docs) = coll.finish();
+            let text = text.into_inner().map_err(|e| e.into_error())?;
+            save_fm(&text, docs, &out, sa_sample, mem_budget_mb)?;
+        }
+
+        Cmd::CodeIndex { dir, out, sa_sample, mem_budget_mb } => {
+            use index::doctable::CollectionWriter;
+            let mut coll = CollectionWriter::new(std::io::BufWriter::new(tempfile::tempfile()?));
+            let lines = index::code::collect_sources(std::path::Path::new(&dir), &mut coll)?;
+            let (text, docs) = coll.finish();
+            let text = text.into_inner().map_err(|e| e.into_error())?;
+            save_fm(&text, docs.with_lines(lines)?, &out, sa_sample, mem_budget_mb)?;
+        }
+
+        Cmd::CodeSearch { index: path, pat, max, regex } => {
+            let fm = index::fmindex::FMIndex::load(&path)?;
+            let hits = if regex {
+                let re = query::fm_regex::FmRegex::new(&pat)?;
+                query::code::code_regex(&fm, &re, max, query::fm_regex::DEFAULT_MAX_NODES)?
             } else {
-                query::phrase::phrase_query(&inv, &terms.iter().map(|s| *s).collect::<Vec<_>>())
+                query::code::code_search(&fm, pat.as_bytes(), max)?
             };
-            println!("hits[{}]: {:?}", hits.len().min(k), &hits.into_iter().take(k).collect::<Vec<_>>());
+            for h in hits {
+                println!("{h}");
+            }
         }
 
-        Cmd::BuildFm { text, out, sa_sample } => {
-            use std::fs::File;
-            use std::io::Read;
-            use index::suffix_array::build_sa;
-            use index::lcp::kasai_lcp;
-            use index::bwt::bwt_from_sa;
-
-            let mut s = String::new();
-            File::open(&text)?.read_to_string(&mut s)?;
-            if !s.ends_with('$') { s.push('$'); }
-            let bytes = s.as_bytes();
-
-            let sa = build_sa(bytes);
-            let _lcp = kasai_lcp(bytes, &sa);
-            let (bwt, _primary) = bwt_from_sa(bytes, &sa);
-            let fm = index::fmindex::FMIndex::build(bytes, &sa, &bwt, sa_sample);
-            fm.save(&out)?;
-            eprintln!("✅ FM-index built and saved to {}", out);
+        Cmd::BuildTrigram { out, inputs } => {
+            let mut b = index::trigram::TrigramBuilder::default();
+            for input in &inputs {
+                let path = std::path::Path::new(input);
+                if path.is_dir() {
+                    index::code::for_each_source(path, |name, bytes| b.add(name, bytes).map(drop))?;
+                } else {
+                    b.add(input, &std::fs::read(path)?)?;
+                }
+            }
+            let idx = b.finalize();
+            idx.save(&out)?;
+            eprintln!("✅ Trigram index built: {} docs, {} trigrams -> {}", idx.docs().len(), idx.n_trigrams(), out);
         }
 
-        Cmd::Substr { fm, pat, max } => {
-            let fm = index::fmindex::FMIndex::load(&fm)?;
+        Cmd::RegexTrigram { index: path, pat, max } => {
+            let idx = index::trigram::TrigramIndex::load(&path)?;
+            let re = query::trigram::TrigramRegex::new(&pat)?;
+            let hits = re.search(&idx, max);
+            println!("candidates = {} of {} docs, matches = {}:", re.candidates(&idx).len(), idx.docs().len(), hits.len());
+            for m in hits {
+                println!("  {}:{} (+{})", idx.docs().label(m.doc), m.off, m.len);
+            }
+        }
+
+        Cmd::Substr { fm: path, pat, max, k, edits, max_nodes } => {
+            use index::fmindex::ApproxMode;
+            let fm = index::fmindex::FMIndex::load(&path)?;
+            if k > 0 {
+                let mode = if edits { ApproxMode::Edit } else { ApproxMode::Hamming };
+                // (offset, len, cost), only hits inside a document
+                let hits = fm.approx_locate(pat.as_bytes(), k, mode, max, max_nodes)?;
+                let more = if hits.len() >= max { "+" } else { "" };
+                println!("matches = {}{more}:", hits.len());
+                for (off, len, cost) in hits {
+                    match fm.docs().and_then(|docs| docs.resolve(off, len).map(|(doc, o)| (docs.label(doc), o))) {
+                        Some((label, o)) => println!("  {label}:{o} (+{len}, cost {cost})"),
+                        None => println!("  {off} (+{len}, cost {cost})"),
+                    }
+                }
+                return Ok(());
+            }
+            if let Some(docs) = fm.docs() {
+                let count = query::substring::substring_count(&fm, docs, pat.as_bytes());
+                let locs = query::substring::substring_locate(&fm, docs, pat.as_bytes(), max);
+                println!("matches = {count}:");
+                for (doc, off) in locs {
+                    println!("  {}:{off}", docs.label(doc));
+                }
+                return Ok(());
+            }
             match fm.backward_search(pat.as_bytes()) {
                 None => println!("no matches"),
                 Some(range) => {
                     let mut locs = fm.locate_range(&range, max);
                     locs.sort_unstable();
-                    println!("matches = {}:", locs.len());
+                    println!("matches = {}:", range.r - range.l);
                     println!("{locs:?}");
                 }
             }
         }
+
+        Cmd::Regex { fm: path, pat, max, max_nodes } => {
+            use query::fm_regex::{regex_locate, FmRegex};
+            let fm = index::fmindex::FMIndex::load(&path)?;
+            let re = FmRegex::new(&pat)?;
+            // in-document matches only when the index has a doc table
+            let hits = regex_locate(&fm, &re, max, max_nodes)?;
+            let more = if hits.len() >= max { "+" } else { "" };
+            println!("matches = {}{more}:", hits.len());
+            for (off, len) in hits {
+                match fm.docs().and_then(|docs| docs.resolve(off, len).map(|(doc, o)| (docs.label(doc), o))) {
+                    Some((label, o)) => println!("  {label}:{o} (+{len})"),
+                    None => println!("  {off} (+{len})"),
+                }
+            }
+        }
+
+        Cmd::Extract { fm: path, start, len, doc } => {
+            let fm = index::fmindex::FMIndex::load(&path)?;
+            let (start, len) = if doc {
+                let docs = fm.docs().ok_or_else(|| anyhow::anyhow!("{path} has no doc table"))?;
+                let Some(span) = docs.span(start as u32) else { anyhow::bail!("no doc {start} ({} docs)", docs.len()) };
+                span
+            } else {
+                (start, len.unwrap_or(fm.text_len().saturating_sub(start)))
+            };
+            use std::io::Write;
+            std::io::stdout().write_all(&fm.extract(start, len)?)?;
+            println!();
+        }
+    }
+    Ok(())
+}
+
+/// Build an FM-index over the bytes of `text` with its doc table embedded,
+/// in memory or (with a budget) on disk.
+fn save_fm(text: &std::fs::File, docs: DocTable, out: &str, sa_sample: usize, mem_budget_mb: Option<usize>) -> Result<()> {
+    docs.check_ids()?;
+    match mem_budget_mb {
+        Some(mb) => {
+            let built = index::external::build_fm_external(text, Some(&docs), out, sa_sample, mb << 20)?;
+            eprintln!("✅ FM-index built on disk ({} bytes, {} sorted runs) and saved to {} ({} docs)",
+                built.text_len, built.runs, out, docs.len());
+        }
+        None => {
+            let bytes = searchkit::util::mmap::mmap_file(text)?;
+            let sa = index::suffix_array::build_sa(&bytes);
+            index::fmindex::FMIndex::build(&bytes, &sa, sa_sample).with_docs(docs.clone()).save(out)?;
+            eprintln!("✅ FM-index built and saved to {} ({} docs)", out, docs.len());
+        }
     }
     Ok(())
 }
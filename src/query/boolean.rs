@@ -1 +1,424 @@
-// TODO
+use crate::index::inverted::InvertedIndex;
+use crate::query::fuzzy::fuzzy_expand;
+use crate::query::phrase::{near_query, phrase_query, sloppy_phrase};
+use crate::query::wildcard::{expand_wildcard, is_wildcard, DEFAULT_MAX_EXPANSIONS};
+use crate::text::tokenize::tokenize;
+use anyhow::*;
+
+/// Parsed boolean query.
+///
+/// Grammar (operators are upper-case, adjacent operands are joined by AND):
+///
+/// ```text
+/// or    := and ("OR" and)*
+/// and   := unary ("AND"? unary)*
+/// unary := "NOT" unary | near
+/// near  := prim (("NEAR/" K | "PRE/" K) prim)*
+/// prim  := "(" or ")" | TERM | "\"" PHRASE "\"" ("~" SLOP)? | "sub:" SUBSTR
+/// ```
+///
+/// A TERM containing `*` or `?` is a wildcard expanded over the dictionary
+/// (`neur*` is a prefix query), and `TERM~N` matches terms within N edits
+/// (`TERM~` means N = 2). `"a b"~N` is a phrase with slop N. `NEAR/k` matches its terms in any order
+/// with at most k tokens between them, `PRE/k` the same in the given order.
+///
+/// `sub:` takes a bare word or a quoted string and matches raw bytes through
+/// the FM-index instead of tokens.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query {
+    Term(String),
+    Wildcard(String),
+    Fuzzy { term: String, k: u32 },
+    Phrase { terms: Vec<String>, slop: u32 },
+    Near { terms: Vec<String>, k: u32, ordered: bool },
+    Substring(String),
+    And(Vec<Query>),
+    Or(Vec<Query>),
+    Not(Box<Query>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    LParen,
+    RParen,
+    And,
+    Or,
+    Not,
+    Word(String),
+    Quoted(String, u32),
+    Near(u32, bool),
+    Substr(String),
+}
+
+fn lex(input: &str) -> Result<Vec<Tok>> {
+    let mut out = Vec::new();
+    let mut chars = input.chars().peekable();
+    while let Some(&ch) = chars.peek() {
+        match ch {
+            c if c.is_whitespace() => { chars.next(); }
+            '(' => { chars.next(); out.push(Tok::LParen); }
+            ')' => { chars.next(); out.push(Tok::RParen); }
+            '"' => {
+                chars.next();
+                let s = read_quoted(&mut chars)?;
+                let mut slop = 0;
+                if chars.peek() == Some(&'~') {
+                    chars.next();
+                    let mut digits = String::new();
+                    while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+                        digits.push(c);
+                        chars.next();
+                    }
+                    slop = digits.parse().context("expected a number after '~' in query")?;
+                }
+                out.push(Tok::Quoted(s, slop));
+            }
+            _ => {
+                let mut s = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '(' || c == ')' || c == '"' { break; }
+                    s.push(c);
+                    chars.next();
+                }
+                if let Some(rest) = s.strip_prefix("sub:") {
+                    let pat = if rest.is_empty() && chars.peek() == Some(&'"') {
+                        chars.next();
+                        read_quoted(&mut chars)?
+                    } else {
+                        rest.to_string()
+                    };
+                    ensure!(!pat.is_empty(), "empty substring in query");
+                    out.push(Tok::Substr(pat));
+                    continue;
+                }
+                let near = [("NEAR/", false), ("PRE/", true)]
+                    .into_iter()
+                    .find_map(|(op, ordered)| s.strip_prefix(op).map(|k| (k, ordered)));
+                if let Some((k, ordered)) = near {
+                    let k = k.parse().with_context(|| format!("bad distance in {s:?}"))?;
+                    out.push(Tok::Near(k, ordered));
+                    continue;
+                }
+                out.push(match s.as_str() {
+                    "AND" => Tok::And,
+                    "OR" => Tok::Or,
+                    "NOT" => Tok::Not,
+                    _ => Tok::Word(s),
+                });
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Read up to the closing quote (the opening one is already consumed).
+fn read_quoted(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<String> {
+    let mut s = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(s),
+            Some(c) => s.push(c),
+            None => bail!("unterminated quote in query"),
+        }
+    }
+}
+
+struct Parser {
+    toks: Vec<Tok>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Tok> { self.toks.get(self.pos) }
+
+    fn next(&mut self) -> Option<Tok> {
+        let t = self.toks.get(self.pos).cloned();
+        self.pos += 1;
+        t
+    }
+
+    fn parse_or(&mut self) -> Result<Query> {
+        let mut parts = vec![self.parse_and()?];
+        while self.peek() == Some(&Tok::Or) {
+            self.next();
+            parts.push(self.parse_and()?);
+        }
+        Ok(if parts.len() == 1 { parts.pop().unwrap() } else { Query::Or(parts) })
+    }
+
+    fn parse_and(&mut self) -> Result<Query> {
+        let mut parts = vec![self.parse_unary()?];
+        loop {
+            match self.peek() {
+                Some(Tok::And) => { self.next(); }
+                Some(Tok::Not | Tok::LParen | Tok::Word(_) | Tok::Quoted(..) | Tok::Substr(_)) => {}
+                _ => break,
+            }
+            parts.push(self.parse_unary()?);
+        }
+        Ok(if parts.len() == 1 { parts.pop().unwrap() } else { Query::And(parts) })
+    }
+
+    fn parse_unary(&mut self) -> Result<Query> {
+        if self.peek() == Some(&Tok::Not) {
+            self.next();
+            return Ok(Query::Not(Box::new(self.parse_unary()?)));
+        }
+        let first = self.parse_primary()?;
+        let Some(&Tok::Near(k, ordered)) = self.peek() else { return Ok(first) };
+        let mut terms = vec![near_operand(first)?];
+        while let Some(&Tok::Near(k2, ordered2)) = self.peek() {
+            ensure!((k2, ordered2) == (k, ordered), "mixed proximity operators in one chain");
+            self.next();
+            terms.push(near_operand(self.parse_primary()?)?);
+        }
+        Ok(Query::Near { terms, k, ordered })
+    }
+
+    fn parse_primary(&mut self) -> Result<Query> {
+        match self.next() {
+            Some(Tok::LParen) => {
+                let q = self.parse_or()?;
+                ensure!(self.next() == Some(Tok::RParen), "expected ')' in query");
+                Ok(q)
+            }
+            Some(Tok::Word(w)) => word_query(&w),
+            Some(Tok::Quoted(s, slop)) => {
+                let terms: Vec<String> = tokenize(&s).into_iter().map(|(t, _)| t).collect();
+                ensure!(!terms.is_empty(), "empty phrase in query");
+                Ok(if terms.len() == 1 { Query::Term(terms[0].clone()) } else { Query::Phrase { terms, slop } })
+            }
+            Some(Tok::Substr(p)) => Ok(Query::Substring(p)),
+            Some(t) => bail!("unexpected {t:?} in query"),
+            None => bail!("unexpected end of query"),
+        }
+    }
+}
+
+fn near_operand(q: Query) -> Result<String> {
+    match q {
+        Query::Term(t) => Ok(t),
+        q => bail!("proximity operands must be single terms, got {q:?}"),
+    }
+}
+
+/// Normalize a bare word with the index tokenizer; words that split into
+/// several tokens (e.g. `state-of-the-art`) become an AND of the pieces.
+fn word_query(w: &str) -> Result<Query> {
+    if is_wildcard(w) { return wildcard_query(w); }
+    if let Some((base, k)) = w.rsplit_once('~') {
+        let k = if k.is_empty() { 2 } else { k.parse().with_context(|| format!("bad edit distance in {w:?}"))? };
+        let Query::Term(term) = word_query(base)? else { bail!("fuzzy operand {base:?} must be a single term") };
+        return Ok(Query::Fuzzy { term, k });
+    }
+    let mut terms: Vec<Query> = tokenize(w).into_iter().map(|(t, _)| Query::Term(t)).collect();
+    ensure!(!terms.is_empty(), "query word {w:?} has no indexable characters");
+    Ok(if terms.len() == 1 { terms.pop().unwrap() } else { Query::And(terms) })
+}
+
+/// A word containing `*` or `?` as a wildcard, lowercased like the
+/// tokenizer lowercases terms.
+pub fn wildcard_query(w: &str) -> Result<Query> {
+    ensure!(
+        w.chars().all(|c| c.is_alphanumeric() || c == '*' || c == '?'),
+        "wildcard {w:?} may only contain letters, digits, '*' and '?'"
+    );
+    Ok(Query::Wildcard(w.to_ascii_lowercase()))
+}
+
+pub fn parse(input: &str) -> Result<Query> {
+    let mut p = Parser { toks: lex(input)?, pos: 0 };
+    ensure!(p.peek().is_some(), "empty query");
+    let q = p.parse_or()?;
+    if let Some(t) = p.peek() { bail!("unexpected {t:?} in query"); }
+    Ok(q)
+}
+
+// ---- sorted doc-id set operations ----
+
+pub fn intersect(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut out = Vec::with_capacity(a.len().min(b.len()));
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => { out.push(a[i]); i += 1; j += 1; }
+        }
+    }
+    out
+}
+
+pub fn union(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut out = Vec::with_capacity(a.len() + b.len());
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => { out.push(a[i]); i += 1; }
+            std::cmp::Ordering::Greater => { out.push(b[j]); j += 1; }
+            std::cmp::Ordering::Equal => { out.push(a[i]); i += 1; j += 1; }
+        }
+    }
+    out.extend_from_slice(&a[i..]);
+    out.extend_from_slice(&b[j..]);
+    out
+}
+
+/// Docs in `a` that are not in `b`.
+pub fn difference(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut out = Vec::with_capacity(a.len());
+    let mut j = 0;
+    for &d in a {
+        while j < b.len() && b[j] < d { j += 1; }
+        if j >= b.len() || b[j] != d { out.push(d); }
+    }
+    out
+}
+
+/// Evaluate a query with caller-supplied leaf resolution. `leaf` is called
+/// for `Term`, `Wildcard`, `Fuzzy`, `Phrase`, `Near` and `Substring` nodes; `universe` (all doc ids) is
+/// only requested when a NOT clause has no positive clause to subtract from.
+pub fn eval_with(
+    q: &Query,
+    leaf: &mut dyn FnMut(&Query) -> Result<Vec<u32>>,
+    universe: &mut dyn FnMut() -> Vec<u32>,
+) -> Result<Vec<u32>> {
+    Ok(match q {
+        Query::Term(_) | Query::Wildcard(_) | Query::Fuzzy { .. } | Query::Phrase { .. } | Query::Near { .. } | Query::Substring(_) => leaf(q)?,
+        Query::Or(qs) => {
+            let mut acc = Vec::new();
+            for q in qs { acc = union(&acc, &eval_with(q, leaf, universe)?); }
+            acc
+        }
+        Query::Not(inner) => difference(&universe(), &eval_with(inner, leaf, universe)?),
+        Query::And(qs) => {
+            let (neg, pos): (Vec<&Query>, Vec<&Query>) =
+                qs.iter().partition(|q| matches!(q, Query::Not(_)));
+            let mut acc = match pos.split_first() {
+                Some((first, rest)) => {
+                    let mut acc = eval_with(first, leaf, universe)?;
+                    for q in rest {
+                        if acc.is_empty() { break; }
+                        acc = intersect(&acc, &eval_with(q, leaf, universe)?);
+                    }
+                    acc
+                }
+                None => universe(),
+            };
+            for q in neg {
+                let Query::Not(inner) = q else { unreachable!() };
+                if acc.is_empty() { break; }
+                acc = difference(&acc, &eval_with(inner, leaf, universe)?);
+            }
+            acc
+        }
+    })
+}
+
+/// Resolve a `Term`, `Wildcard`, `Fuzzy`, `Phrase` or `Near` leaf against
+/// the inverted index; wildcards and fuzzy terms expand to at most
+/// `max_expansions` dictionary terms.
+pub fn index_leaf(inv: &InvertedIndex, q: &Query, max_expansions: usize) -> Result<Vec<u32>> {
+    match q {
+        Query::Term(t) => Ok(inv.postings(t).into_iter().map(|(d, _)| d).collect()),
+        Query::Wildcard(p) => {
+            let mut acc = Vec::new();
+            for t in expand_wildcard(inv, p, max_expansions)? {
+                let docs: Vec<u32> = inv.postings(&t).into_iter().map(|(d, _)| d).collect();
+                acc = union(&acc, &docs);
+            }
+            Ok(acc)
+        }
+        Query::Fuzzy { term, k } => {
+            let mut acc = Vec::new();
+            for (t, _) in fuzzy_expand(inv, term, *k, max_expansions)? {
+                let docs: Vec<u32> = inv.postings(&t).into_iter().map(|(d, _)| d).collect();
+                acc = union(&acc, &docs);
+            }
+            Ok(acc)
+        }
+        Query::Phrase { terms, slop: 0 } => {
+            let terms: Vec<&str> = terms.iter().map(|s| s.as_str()).collect();
+            Ok(phrase_query(inv, &terms))
+        }
+        Query::Phrase { terms, slop } => {
+            let terms: Vec<&str> = terms.iter().map(|s| s.as_str()).collect();
+            Ok(sloppy_phrase(inv, &terms, *slop).into_iter().map(|(d, _)| d).collect())
+        }
+        Query::Near { terms, k, ordered } => {
+            let terms: Vec<&str> = terms.iter().map(|s| s.as_str()).collect();
+            Ok(near_query(inv, &terms, *k, *ordered).into_iter().map(|(d, _)| d).collect())
+        }
+        _ => bail!("{q:?} cannot be answered from the inverted index alone"),
+    }
+}
+
+/// Evaluate a query against the inverted index alone, returning the sorted
+/// list of matching doc ids, with [`DEFAULT_MAX_EXPANSIONS`] per wildcard.
+/// The universe for NOT is `0..n_docs` of the index.
+pub fn eval(inv: &InvertedIndex, q: &Query) -> Result<Vec<u32>> {
+    eval_with(q, &mut |leaf| index_leaf(inv, leaf, DEFAULT_MAX_EXPANSIONS), &mut || (0..inv.n_docs).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::inverted::InvBuilder;
+
+    fn index() -> InvertedIndex {
+        let mut b = InvBuilder::default();
+        b.add_doc(0, "Neural networks are cool.");
+        b.add_doc(1, "Cats are smarter than dogs.");
+        b.add_doc(2, "Neural network training uses gradients.");
+        b.add_doc(3, "A network of cats.");
+        b.finalize()
+    }
+
+    #[test]
+    fn parse_precedence() {
+        let q = parse("a b OR NOT c").unwrap();
+        assert_eq!(q, Query::Or(vec![
+            Query::And(vec![Query::Term("a".into()), Query::Term("b".into())]),
+            Query::Not(Box::new(Query::Term("c".into()))),
+        ]));
+        assert!(parse("(a OR b").is_err());
+        assert!(parse("a AND").is_err());
+        assert_eq!(parse("\"neural training\"~3 a NEAR/2 b").unwrap(), Query::And(vec![
+            Query::Phrase { terms: vec!["neural".into(), "training".into()], slop: 3 },
+            Query::Near { terms: vec!["a".into(), "b".into()], k: 2, ordered: false },
+        ]));
+        assert!(parse("a NEAR/2 b PRE/2 c").is_err());
+        assert_eq!(parse("Neur* OR n?t").unwrap(), Query::Or(vec![
+            Query::Wildcard("neur*".into()),
+            Query::Wildcard("n?t".into()),
+        ]));
+        assert_eq!(parse("Netwrk~1 cats~").unwrap(), Query::And(vec![
+            Query::Fuzzy { term: "netwrk".into(), k: 1 },
+            Query::Fuzzy { term: "cats".into(), k: 2 },
+        ]));
+        assert_eq!(parse("sub:\"s ar\" x").unwrap(), Query::And(vec![
+            Query::Substring("s ar".into()),
+            Query::Term("x".into()),
+        ]));
+    }
+
+    #[test]
+    fn eval_ops() {
+        let inv = index();
+        assert_eq!(eval(&inv, &parse("(neural OR network) AND NOT cats").unwrap()).unwrap(), vec![0, 2]);
+        assert_eq!(eval(&inv, &parse("network cats").unwrap()).unwrap(), vec![3]);
+        assert_eq!(eval(&inv, &parse("network* AND NOT neural").unwrap()).unwrap(), vec![3]);
+        assert_eq!(eval(&inv, &parse("NOT network").unwrap()).unwrap(), vec![0, 1]);
+        // network, networks
+        assert_eq!(index_leaf(&inv, &parse("netw*").unwrap(), 2).unwrap(), vec![0, 2, 3]);
+        assert!(index_leaf(&inv, &parse("netw*").unwrap(), 1).is_err());
+
+        // docs without postings are still in the NOT universe
+        let mut b = InvBuilder::default();
+        b.add_doc(0, "cats");
+        b.add_doc(1, "");
+        b.add_doc(2, "dogs");
+        b.add_doc(3, "");
+        assert_eq!(eval(&b.finalize(), &parse("NOT cats").unwrap()).unwrap(), vec![1, 2, 3]);
+    }
+}
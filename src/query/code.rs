@@ -0,0 +1,121 @@
+use crate::index::doctable::DocTable;
+use crate::index::fmindex::FMIndex;
+use crate::query::fm_regex::{regex_search, FmRegex, RegexRange};
+use anyhow::*;
+use std::collections::BTreeMap;
+
+/// Longest line text printed for one hit (minified files can be huge).
+pub const MAX_LINE_BYTES: usize = 512;
+
+/// One match in a code index, printed as `path:line:col: text`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodeHit {
+    pub path: String,
+    pub line: usize,
+    pub col: usize,
+    pub text: String,
+}
+
+impl std::fmt::Display for CodeHit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.path, self.line, self.col, self.text)
+    }
+}
+
+/// The first `limit` occurrences of `pat`, by path order then position.
+pub fn code_search(fm: &FMIndex, pat: &[u8], limit: usize) -> Result<Vec<CodeHit>> {
+    let docs = fm.docs().context("index has no doc table")?;
+    let ranges = fm.backward_search(pat).map(|range| RegexRange { range, len: pat.len() });
+    to_hits(fm, first_locs(fm, docs, ranges.into_iter().collect(), limit))
+}
+
+/// As [`code_search`] for a regex, evaluated on the FM-index.
+pub fn code_regex(fm: &FMIndex, re: &FmRegex, limit: usize, max_nodes: usize) -> Result<Vec<CodeHit>> {
+    let docs = fm.docs().context("index has no doc table")?;
+    to_hits(fm, first_locs(fm, docs, regex_search(fm, re, max_nodes)?, limit))
+}
+
+/// The `limit` smallest in-document match offsets in `ranges` as
+/// `(doc, offset)`, sorted. Finding the first matches means locating every
+/// row, but only `limit` of them are kept (and later extracted). Longer
+/// ranges go first so an offset keeps its longest in-document match.
+fn first_locs(fm: &FMIndex, docs: &DocTable, mut ranges: Vec<RegexRange>, limit: usize) -> Vec<(u32, usize)> {
+    ranges.sort_by_key(|rr| std::cmp::Reverse(rr.len));
+    let mut first: BTreeMap<usize, (u32, usize)> = BTreeMap::new();
+    for rr in ranges {
+        for r in rr.range.l..rr.range.r {
+            let off = fm.locate(r);
+            if first.contains_key(&off) || (first.len() >= limit && first.last_key_value().is_some_and(|(&k, _)| off > k)) {
+                continue;
+            }
+            let Some(loc) = docs.resolve(off, rr.len) else { continue };
+            first.insert(off, loc);
+            if first.len() > limit {
+                first.pop_last();
+            }
+        }
+    }
+    first.into_values().collect()
+}
+
+/// `(doc, offset)` matches as hits, using the doc table's line starts.
+fn to_hits(fm: &FMIndex, locs: Vec<(u32, usize)>) -> Result<Vec<CodeHit>> {
+    let docs = fm.docs().context("index has no doc table")?;
+    let lines = docs.lines().context("index has no line table (build it with code-index)")?;
+    let mut out = Vec::with_capacity(locs.len());
+    for (doc, off) in locs {
+        let (start, len) = docs.span(doc).context("doc out of range")?;
+        let pos = lines.locate(doc, off, len).context("offset outside line table")?;
+        let line_len = (pos.end - pos.start).min(MAX_LINE_BYTES);
+        let text = fm.extract(start + pos.start, line_len)?;
+        out.push(CodeHit {
+            path: docs.label(doc),
+            line: pos.line,
+            col: pos.col,
+            text: String::from_utf8_lossy(&text).trim_end_matches(['\r', '\n']).to_string(),
+        });
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::code::LineTable;
+    use crate::index::doctable::CollectionWriter;
+    use crate::index::suffix_array::build_sa;
+
+    #[test]
+    fn hits_map_to_path_line_col() {
+        let mut coll = CollectionWriter::new(Vec::new());
+        let mut lines = LineTable::default();
+        for (name, body) in [("a.rs", "fn a() {}\nfn b() { a(); }\n"), ("b.rs", "// a()\r\nlet x = a();")] {
+            coll.add(name, body.as_bytes()).unwrap();
+            lines.push_doc(body.as_bytes());
+        }
+        let (text, docs) = coll.finish();
+        let bare = FMIndex::build(&text, &build_sa(&text), 4).with_docs(docs.clone());
+        assert!(code_search(&bare, b"a()", 10).is_err());
+        let fm = bare.with_docs(docs.with_lines(lines).unwrap());
+
+        let hits: Vec<String> = code_search(&fm, b"a()", 10).unwrap().iter().map(|h| h.to_string()).collect();
+        assert_eq!(hits, [
+            "a.rs:1:4: fn a() {}",
+            "a.rs:2:10: fn b() { a(); }",
+            "b.rs:1:4: // a()",
+            "b.rs:2:9: let x = a();",
+        ]);
+        let first: Vec<String> = code_search(&fm, b"a()", 2).unwrap().iter().map(|h| h.to_string()).collect();
+        assert_eq!(first, hits[..2]);
+        assert!(code_search(&fm, b"a()", 0).unwrap().is_empty());
+
+        let re = FmRegex::new(r"fn [ab]\(").unwrap();
+        let hits = code_regex(&fm, &re, 1, usize::MAX).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].to_string(), "a.rs:1:1: fn a() {}");
+        // the longest in-document match wins, not one running into b.rs
+        let re = FmRegex::new(r"\}\s*(// )?").unwrap();
+        let hits: Vec<String> = code_regex(&fm, &re, 10, usize::MAX).unwrap().iter().map(|h| h.to_string()).collect();
+        assert_eq!(hits, ["a.rs:1:9: fn a() {}", "a.rs:2:15: fn b() { a(); }"]);
+    }
+}
@@ -1 +1,272 @@
-// TODO
+use crate::index::fmindex::FMIndex;
+use crate::index::inverted::InvertedIndex;
+use crate::query::boolean::{self, Query};
+use crate::query::fuzzy::{fuzzy_expand, fuzzy_weight};
+use crate::query::phrase::{near_query, sloppy_phrase, Window};
+use crate::query::substring::{substring_docs, DocTable};
+use crate::query::wildcard::{expand_wildcard, is_wildcard, merged_postings, DEFAULT_MAX_EXPANSIONS};
+use crate::rank::bm25::BM25;
+use crate::rank::similarity::{top_k, Similarity, TermPostings};
+use anyhow::*;
+use std::collections::BTreeMap;
+
+pub use crate::rank::similarity::{CollectionStats, Hit};
+
+/// Single entry point for answering queries: matching is done by the boolean
+/// evaluator, leaves are resolved against the inverted index (terms, phrases)
+/// or the FM-index (substrings), and matches are ranked with a
+/// [`Similarity`] (BM25 unless set) over the query's positive terms. Phrase
+/// and proximity clauses score as a unit, with each match window counting
+/// `1 / (cost + 1)` towards the frequency.
+pub struct QueryEngine {
+    inv: InvertedIndex,
+    fm: Option<(FMIndex, DocTable)>,
+    stats: CollectionStats,
+    sim: Box<dyn Similarity>,
+    max_expansions: usize,
+}
+
+impl QueryEngine {
+    pub fn new(inv: InvertedIndex) -> Self {
+        let stats = CollectionStats::from_index(&inv, None);
+        Self::with_stats(inv, stats)
+    }
+
+    pub fn with_stats(inv: InvertedIndex, stats: CollectionStats) -> Self {
+        Self { inv, fm: None, stats, sim: Box::new(BM25::default()), max_expansions: DEFAULT_MAX_EXPANSIONS }
+    }
+
+    /// Rank with `sim` instead of default BM25, computing the doc norms it
+    /// needs.
+    pub fn with_similarity(mut self, sim: impl Similarity + 'static) -> Result<Self> {
+        self.stats = self.stats.with_doc_norms(&self.inv, &sim)?;
+        self.sim = Box::new(sim);
+        Ok(self)
+    }
+
+    /// Enable substring queries; `docs` must use the same doc ids as the
+    /// inverted index.
+    pub fn with_fm(mut self, fm: FMIndex, docs: DocTable) -> Self {
+        self.fm = Some((fm, docs));
+        self
+    }
+
+    /// Cap on dictionary terms a single wildcard may expand to.
+    pub fn with_max_expansions(mut self, max: usize) -> Self {
+        self.max_expansions = max;
+        self
+    }
+
+    pub fn index(&self) -> &InvertedIndex { &self.inv }
+    pub fn fm(&self) -> Option<&FMIndex> { self.fm.as_ref().map(|(fm, _)| fm) }
+    pub fn stats(&self) -> &CollectionStats { &self.stats }
+
+    /// Free-text query: every token is optional (OR), ranking does the rest.
+    /// Words containing `*` or `?` are kept whole as wildcards (see
+    /// [`boolean::wildcard_query`]); `None` if there is no token at all.
+    pub fn parse_free_text(text: &str) -> Result<Option<Query>> {
+        let mut terms: Vec<Query> = Vec::new();
+        for word in text.split_whitespace() {
+            if is_wildcard(word) {
+                terms.push(boolean::wildcard_query(word)?);
+            } else {
+                terms.extend(crate::text::tokenize::tokenize(word).into_iter().map(|(t, _)| Query::Term(t)));
+            }
+        }
+        Ok(match terms.len() {
+            0 => None,
+            1 => terms.pop(),
+            _ => Some(Query::Or(terms)),
+        })
+    }
+
+    fn leaf(&self, q: &Query) -> Result<Vec<u32>> {
+        match q {
+            Query::Wildcard(_) | Query::Fuzzy { .. } => {
+                let terms: Vec<String> = self.expand(q)?.into_iter().map(|(t, _)| t).collect();
+                Ok(merged_postings(&self.inv, &terms).into_iter().map(|(d, _)| d).collect())
+            }
+            Query::Substring(pat) => {
+                let (fm, docs) = self.fm.as_ref().context("substring query needs an FM-index")?;
+                Ok(substring_docs(fm, docs, pat.as_bytes()).into_iter().map(|m| m.doc).collect())
+            }
+            _ => boolean::index_leaf(&self.inv, q, self.max_expansions),
+        }
+    }
+
+    /// Sorted doc ids matching `q`.
+    pub fn matches(&self, q: &Query) -> Result<Vec<u32>> {
+        let n_docs = self.stats.n_docs;
+        boolean::eval_with(q, &mut |leaf| self.leaf(leaf), &mut || (0..n_docs).collect())
+    }
+
+    /// Token windows matched by each positive clause of `q`, per doc: one
+    /// single-token window per term occurrence plus phrase/proximity
+    /// windows. Meant for highlighting (see `InvertedIndex::offsets`).
+    pub fn windows(&self, q: &Query) -> Result<BTreeMap<u32, Vec<Window>>> {
+        let mut out: BTreeMap<u32, Vec<Window>> = BTreeMap::new();
+        let mut clauses = Vec::new();
+        positive_clauses(q, &mut clauses);
+        for clause in clauses {
+            for (doc, wins) in self.clause_windows(clause)? {
+                out.entry(doc).or_default().extend(wins);
+            }
+        }
+        for wins in out.values_mut() {
+            wins.sort_unstable_by_key(|w| (w.start, w.end));
+            wins.dedup();
+        }
+        Ok(out)
+    }
+
+    /// Dictionary terms a single-term clause stands for, with score weights
+    /// (1 for exact and wildcard terms, distance-dependent for fuzzy ones).
+    fn expand(&self, clause: &Query) -> Result<Vec<(String, f32)>> {
+        Ok(match clause {
+            Query::Term(t) => vec![(t.clone(), 1.0)],
+            Query::Wildcard(p) => {
+                expand_wildcard(&self.inv, p, self.max_expansions)?.into_iter().map(|t| (t, 1.0)).collect()
+            }
+            Query::Fuzzy { term, k } => fuzzy_expand(&self.inv, term, *k, self.max_expansions)?
+                .into_iter()
+                .map(|(t, d)| {
+                    let w = fuzzy_weight(term, &t, d);
+                    (t, w)
+                })
+                .collect(),
+            _ => vec![],
+        })
+    }
+
+    fn clause_windows(&self, clause: &Query) -> Result<Vec<(u32, Vec<Window>)>> {
+        Ok(match clause {
+            Query::Term(_) | Query::Wildcard(_) | Query::Fuzzy { .. } => {
+                let terms: Vec<String> = self.expand(clause)?.into_iter().map(|(t, _)| t).collect();
+                merged_postings(&self.inv, &terms)
+                    .into_iter()
+                    .map(|(d, ps)| (d, ps.into_iter().map(|p| Window { start: p, end: p, cost: 0 }).collect()))
+                    .collect()
+            }
+            Query::Phrase { terms, slop } => {
+                sloppy_phrase(&self.inv, &terms.iter().map(|s| s.as_str()).collect::<Vec<_>>(), *slop)
+            }
+            Query::Near { terms, k, ordered } => {
+                near_query(&self.inv, &terms.iter().map(|s| s.as_str()).collect::<Vec<_>>(), *k, *ordered)
+            }
+            _ => vec![],
+        })
+    }
+
+    /// Matching docs ranked over the positive clauses of `q`, best first;
+    /// docs matched only through NOT or substrings score 0.
+    pub fn search(&self, q: &Query, k: usize) -> Result<Vec<Hit>> {
+        let docs = self.matches(q)?;
+        let mut all = Vec::new();
+        positive_clauses(q, &mut all);
+        // a clause repeated anywhere in the query is scored once, with the
+        // repeats as its query term frequency
+        let mut clauses: Vec<(&Query, f32)> = Vec::with_capacity(all.len());
+        for c in all {
+            match clauses.iter_mut().find(|(q, _)| *q == c) {
+                Some((_, qtf)) => *qtf += 1.0,
+                None => clauses.push((c, 1.0)),
+            }
+        }
+        let mut terms = Vec::with_capacity(clauses.len());
+        for (clause, qtf) in clauses {
+            terms.push(match clause {
+                // expanded terms merge into one clause; each occurrence
+                // counts with its term's weight
+                Query::Term(_) | Query::Wildcard(_) | Query::Fuzzy { .. } => {
+                    let mut tf: BTreeMap<u32, f32> = BTreeMap::new();
+                    let mut cf = 0.0;
+                    for (t, w) in self.expand(clause)? {
+                        for (d, ps) in self.inv.postings(&t) {
+                            *tf.entry(d).or_insert(0.0) += w * ps.len() as f32;
+                        }
+                        cf += w * self.inv.cf(&t) as f32;
+                    }
+                    TermPostings::new(tf.into_iter().collect()).with_cf(cf)
+                }
+                _ => TermPostings::new(
+                    self.clause_windows(clause)?
+                        .into_iter()
+                        .map(|(d, ws)| (d, ws.iter().map(|w| 1.0 / (w.cost as f32 + 1.0)).sum()))
+                        .collect(),
+                ),
+            }
+            .with_qtf(qtf));
+        }
+        Ok(top_k(&*self.sim, &self.stats, &terms, Some(&docs), k))
+    }
+}
+
+/// Index-backed leaves that contribute to the score: everything not under
+/// a NOT.
+fn positive_clauses<'a>(q: &'a Query, out: &mut Vec<&'a Query>) {
+    match q {
+        Query::Term(_) | Query::Wildcard(_) | Query::Fuzzy { .. } | Query::Phrase { .. } | Query::Near { .. } => {
+            out.push(q)
+        }
+        Query::And(qs) | Query::Or(qs) => qs.iter().for_each(|q| positive_clauses(q, out)),
+        Query::Not(_) | Query::Substring(_) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::inverted::InvBuilder;
+
+    #[test]
+    fn ranks_boolean_matches() {
+        let mut b = InvBuilder::default();
+        b.add_doc(0, "neural networks are cool");
+        b.add_doc(1, "cats are smarter than dogs");
+        b.add_doc(2, "neural network training uses neural gradients");
+        b.add_doc(3, "dogs chase cats");
+        b.add_doc(4, "the cat sat on the mat");
+        b.add_doc(5, "gradient descent");
+        b.add_doc(6, "stochastic gradient descent");
+        let engine = QueryEngine::new(b.finalize());
+        assert_eq!(engine.stats().n_docs, 7);
+
+        let hits = engine.search(&boolean::parse("neural AND NOT cats").unwrap(), 10).unwrap();
+        assert_eq!(hits.iter().map(|h| h.doc).collect::<Vec<_>>(), vec![2, 0]);
+
+        let q = boolean::parse("cats NEAR/1 dogs").unwrap();
+        assert_eq!(engine.matches(&q).unwrap(), vec![3]);
+        let wins = engine.windows(&q).unwrap();
+        assert_eq!(wins.into_iter().collect::<Vec<_>>(), vec![(3, vec![Window { start: 0, end: 2, cost: 1 }])]);
+
+        // exact spelling outranks a one-edit neighbour at equal tf
+        let hits = engine.search(&boolean::parse("cats~1").unwrap(), 10).unwrap();
+        assert_eq!(hits.iter().map(|h| h.doc).collect::<Vec<_>>(), vec![3, 1, 4]);
+
+        let once = engine.search(&boolean::parse("cats OR dogs").unwrap(), 10).unwrap();
+        assert_eq!(engine.search(&boolean::parse("cats OR dogs OR cats").unwrap(), 10).unwrap(), once);
+
+        let hits = engine.search(&boolean::parse("NOT (neural OR dogs OR gradient)").unwrap(), 10).unwrap();
+        assert_eq!(hits, vec![Hit { doc: 4, score: 0.0 }]);
+    }
+
+    #[test]
+    fn free_text_wildcards() {
+        let mut b = InvBuilder::default();
+        b.add_doc(0, "Über cats");
+        b.add_doc(1, "neural networks");
+        let engine = QueryEngine::new(b.finalize());
+        // lowercased like the tokenizer: ASCII only, so Ü stays upper case
+        let q = QueryEngine::parse_free_text("ÜBER* NEUR*").unwrap().unwrap();
+        assert_eq!(q, Query::Or(vec![Query::Wildcard("Über*".into()), Query::Wildcard("neur*".into())]));
+        assert_eq!(engine.matches(&q).unwrap(), vec![0, 1]);
+        assert!(QueryEngine::parse_free_text("net-*").is_err());
+        assert_eq!(QueryEngine::parse_free_text(" ,; ").unwrap(), None);
+
+        // "n*" expands to two terms: over the cap, highlighting fails like search
+        let engine = engine.with_max_expansions(1);
+        let q = boolean::parse("n*").unwrap();
+        assert!(engine.search(&q, 10).is_err());
+        assert!(engine.windows(&q).is_err());
+    }
+}
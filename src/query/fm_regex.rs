@@ -0,0 +1,136 @@
+use crate::index::fmindex::{FMIndex, MatchRange};
+use anyhow::*;
+use regex_automata::dfa::{dense, Automaton, StartKind};
+use regex_automata::nfa::thompson;
+use regex_automata::util::primitives::StateID;
+use regex_automata::util::start;
+use regex_automata::{Anchored, MatchKind};
+use std::collections::BTreeMap;
+
+/// Default cap on nodes (distinct text substrings) visited by one search.
+pub const DEFAULT_MAX_NODES: usize = 1_000_000;
+
+/// A regex compiled for backward search: a DFA over the *reversed* pattern,
+/// so it can be driven by LF steps that extend a match one byte to the left.
+pub struct FmRegex {
+    dfa: dense::DFA<Vec<u32>>,
+}
+
+/// SA rows whose suffixes start with a match of `len` bytes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegexRange {
+    pub range: MatchRange,
+    pub len: usize,
+}
+
+impl FmRegex {
+    /// Compile `pattern`. Look-around (`^`, `$`, `\b`) and patterns that can
+    /// match the empty string are rejected: neither means anything for "rows
+    /// of the suffix array".
+    pub fn new(pattern: &str) -> Result<Self> {
+        let hir = regex_syntax::Parser::new().parse(pattern)?;
+        ensure!(hir.properties().look_set().is_empty(), "anchors and word boundaries are not supported: {pattern:?}");
+        ensure!(hir.properties().minimum_len().unwrap_or(0) > 0, "pattern {pattern:?} can match the empty string");
+        let dfa = dense::Builder::new()
+            // All: every match length must stay reachable, not just the
+            // preferred one
+            .configure(dense::Config::new().start_kind(StartKind::Anchored).match_kind(MatchKind::All))
+            .thompson(thompson::Config::new().reverse(true))
+            .build(pattern)?;
+        Ok(Self { dfa })
+    }
+
+    fn start(&self) -> Result<StateID> {
+        Ok(self.dfa.start_state(&start::Config::new().anchored(Anchored::Yes))?)
+    }
+}
+
+/// All SA ranges of regex matches, found by walking the reversed DFA over the
+/// BWT. Each node extends the current match left by one byte via
+/// `FMIndex::extend_left`, so only strings that occur in the text are ever
+/// explored, and each of them once (a range plus a length names a single
+/// substring). Fails once more than `max_nodes` nodes were visited.
+pub fn regex_search(fm: &FMIndex, re: &FmRegex, max_nodes: usize) -> Result<Vec<RegexRange>> {
+    let dfa = &re.dfa;
+    let mut out = Vec::new();
+    let mut nodes = 0usize;
+    let mut stack = vec![(re.start()?, fm.full_range(), 0usize)];
+    while let Some((s, range, len)) = stack.pop() {
+        if len > 0 && dfa.is_match_state(dfa.next_eoi_state(s)) {
+            out.push(RegexRange { range, len });
+        }
+        for b in 0..=255u8 {
+            let t = dfa.next_state(s, b);
+            if dfa.is_dead_state(t) || dfa.is_quit_state(t) { continue; }
+            let Some(next) = fm.extend_left(&range, b) else { continue };
+            nodes += 1;
+            ensure!(nodes <= max_nodes, "regex search visited more than {max_nodes} nodes");
+            stack.push((t, next, len + 1));
+        }
+    }
+    Ok(out)
+}
+
+/// Match start offsets with the longest match length found there, sorted,
+/// at most `limit` of them. With a doc table only matches inside one document
+/// count, so a match that runs over a separator falls back to its shorter
+/// in-document prefixes. Ranges are located longest first until `limit`
+/// offsets are found: with more matches than that, these are some `limit` of
+/// them, each with its longest length.
+pub fn regex_locate(fm: &FMIndex, re: &FmRegex, limit: usize, max_nodes: usize) -> Result<Vec<(usize, usize)>> {
+    let mut ranges = regex_search(fm, re, max_nodes)?;
+    ranges.sort_by_key(|rr| std::cmp::Reverse(rr.len));
+    let mut best: BTreeMap<usize, usize> = BTreeMap::new();
+    'ranges: for rr in ranges {
+        for r in rr.range.l..rr.range.r {
+            if best.len() >= limit {
+                break 'ranges;
+            }
+            let off = fm.locate(r);
+            if fm.docs().is_some_and(|docs| docs.resolve(off, rr.len).is_none()) {
+                continue;
+            }
+            best.entry(off).or_insert(rr.len);
+        }
+    }
+    Ok(best.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::doctable::CollectionWriter;
+    use crate::index::suffix_array::build_sa;
+
+    #[test]
+    fn regex_on_bwt() {
+        let s = b"the cat sat on the mat; a cart.";
+        let fm = FMIndex::build(s, &build_sa(s), 3);
+
+        let re = FmRegex::new("[cm]a?r?t").unwrap();
+        let hits = regex_locate(&fm, &re, 10, DEFAULT_MAX_NODES).unwrap();
+        assert_eq!(hits, vec![(4, 3), (19, 3), (26, 4)]);
+
+        let re = FmRegex::new("(th|[so]n)e?").unwrap();
+        assert_eq!(regex_locate(&fm, &re, 10, DEFAULT_MAX_NODES).unwrap(), vec![(0, 3), (12, 2), (15, 3)]);
+
+        assert_eq!(regex_locate(&fm, &re, 2, DEFAULT_MAX_NODES).unwrap().len(), 2);
+        assert!(FmRegex::new("a*").is_err());
+        assert!(FmRegex::new("^the").is_err());
+    }
+
+    #[test]
+    fn matches_stop_at_document_ends() {
+        let mut coll = CollectionWriter::new(Vec::new());
+        coll.add("a", b"x").unwrap();
+        coll.add("b", b"  y").unwrap();
+        let (text, docs) = coll.finish();
+        let bare = FMIndex::build(&text, &build_sa(&text), 2);
+        let re = FmRegex::new(r"x\s*").unwrap();
+        assert_eq!(regex_locate(&bare, &re, 10, DEFAULT_MAX_NODES).unwrap(), vec![(0, 4)]);
+        let fm = bare.with_docs(docs);
+        let hits = regex_locate(&fm, &re, 10, DEFAULT_MAX_NODES).unwrap();
+        assert_eq!(hits, vec![(0, 1)]);
+        assert_eq!(fm.docs().unwrap().resolve(0, 1), Some((0, 0)));
+    }
+}
@@ -0,0 +1,136 @@
+use crate::index::inverted::InvertedIndex;
+use anyhow::*;
+use std::ops::Bound;
+
+/// Levenshtein automaton for one query term. A state is the DP row of edit
+/// distances between the query and the input consumed so far; rows are
+/// clamped at `k + 1`, so the state space is finite.
+pub struct LevenshteinAutomaton {
+    query: Vec<char>,
+    k: u32,
+}
+
+impl LevenshteinAutomaton {
+    pub fn new(query: &str, k: u32) -> Self {
+        Self { query: query.chars().collect(), k }
+    }
+
+    pub fn start(&self) -> Vec<u32> {
+        (0..=self.query.len() as u32).map(|d| d.min(self.k + 1)).collect()
+    }
+
+    pub fn step(&self, row: &[u32], c: char) -> Vec<u32> {
+        let mut next = Vec::with_capacity(row.len());
+        next.push((row[0] + 1).min(self.k + 1));
+        for (i, &q) in self.query.iter().enumerate() {
+            let sub = row[i] + (q != c) as u32;
+            let v = sub.min(row[i + 1] + 1).min(next[i] + 1);
+            next.push(v.min(self.k + 1));
+        }
+        next
+    }
+
+    /// Distance if the input so far is accepted.
+    pub fn distance(&self, row: &[u32]) -> Option<u32> {
+        let d = *row.last().unwrap();
+        (d <= self.k).then_some(d)
+    }
+
+    /// False once no continuation can be accepted.
+    pub fn can_match(&self, row: &[u32]) -> bool {
+        row.iter().any(|&d| d <= self.k)
+    }
+}
+
+/// Smallest string greater than every string starting with `prefix`.
+fn prefix_successor(prefix: &str) -> Option<String> {
+    let mut chars: Vec<char> = prefix.chars().collect();
+    while let Some(c) = chars.pop() {
+        let next = (c as u32 + 1..=char::MAX as u32).find_map(char::from_u32);
+        if let Some(n) = next {
+            chars.push(n);
+            return Some(chars.into_iter().collect());
+        }
+    }
+    None
+}
+
+/// Dictionary terms within `k` edits of `term`, with their distances, in
+/// term order. Walks the sorted dictionary once, sharing automaton states
+/// across common prefixes and seeking past prefixes that can no longer match.
+/// Errors if more than `max` terms match.
+pub fn fuzzy_expand(inv: &InvertedIndex, term: &str, k: u32, max: usize) -> Result<Vec<(String, u32)>> {
+    let lev = LevenshteinAutomaton::new(term, k);
+    let mut out = Vec::new();
+    let mut stack: Vec<Vec<u32>> = vec![lev.start()]; // stack[i] = state after i chars of `prev`
+    let mut prev: Vec<char> = Vec::new();
+    let mut cursor = String::new();
+    'seek: loop {
+        let range = inv.dict.range::<str, _>((Bound::Included(cursor.as_str()), Bound::Unbounded));
+        for cand in range.map(|(t, _)| t) {
+            let chars: Vec<char> = cand.chars().collect();
+            let common = prev.iter().zip(&chars).take_while(|(a, b)| a == b).count();
+            stack.truncate(common + 1);
+            let mut dead = false;
+            for &c in &chars[common..] {
+                let row = lev.step(stack.last().unwrap(), c);
+                dead = !lev.can_match(&row);
+                stack.push(row);
+                if dead { break; }
+            }
+            prev = chars;
+            if dead {
+                // every term sharing this prefix is out of reach
+                let dead_prefix: String = prev[..stack.len() - 1].iter().collect();
+                match prefix_successor(&dead_prefix) {
+                    Some(next) => { cursor = next; prev.clear(); stack.truncate(1); continue 'seek; }
+                    None => break 'seek,
+                }
+            }
+            if let Some(d) = lev.distance(stack.last().unwrap()) {
+                ensure!(out.len() < max, "fuzzy term {term:?}~{k} expands to more than {max} terms");
+                out.push((cand.clone(), d));
+            }
+        }
+        break;
+    }
+    Ok(out)
+}
+
+/// Score weight of an expansion at distance `d`, as in Lucene's fuzzy
+/// query: `1 - d / min(len(query), len(term))`, floored at 0.
+pub fn fuzzy_weight(query: &str, term: &str, d: u32) -> f32 {
+    let len = query.chars().count().min(term.chars().count()).max(1);
+    (1.0 - d as f32 / len as f32).max(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::inverted::InvBuilder;
+
+    #[test]
+    fn automaton_distances() {
+        let lev = LevenshteinAutomaton::new("network", 2);
+        let run = |s: &str| lev.distance(&s.chars().fold(lev.start(), |row, c| lev.step(&row, c)));
+        assert_eq!(run("network"), Some(0));
+        assert_eq!(run("netwrk"), Some(1));
+        assert_eq!(run("netwrks"), Some(2));
+        assert_eq!(run("nteworks"), None);
+        assert_eq!(run("neural"), None);
+    }
+
+    #[test]
+    fn expand_over_dictionary() {
+        let mut b = InvBuilder::default();
+        b.add_doc(0, "neural networks network netwerk nest zebra");
+        let inv = b.finalize();
+        assert_eq!(fuzzy_expand(&inv, "network", 1, 10).unwrap(), vec![
+            ("netwerk".to_string(), 1),
+            ("network".to_string(), 0),
+            ("networks".to_string(), 1),
+        ]);
+        assert!(fuzzy_expand(&inv, "network", 1, 2).is_err());
+        assert_eq!(prefix_successor("ab"), Some("ac".to_string()));
+    }
+}
@@ -23,3 +23,116 @@ pub fn phrase_query(inv:&InvertedIndex, terms:&[&str])->Vec<u32>{
         } cur=merged;
     } cur.into_iter().map(|(d,_)| d).collect()
 }
+
+/// A matched span in token ordinals (inclusive) with its proximity cost:
+/// Lucene-style match length for sloppy phrases, intervening tokens for NEAR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window { pub start: u32, pub end: u32, pub cost: u32 }
+
+/// Postings of every term, restricted to docs that contain all of them.
+fn conjunctive_postings(inv:&InvertedIndex, terms:&[&str])->Vec<(u32,Vec<Vec<u32>>)>{
+    let lists:Vec<Vec<(u32,Vec<u32>)>>=terms.iter().map(|t| inv.postings(t)).collect();
+    if lists.iter().any(|l| l.is_empty()){ return vec![]; }
+    let mut idx=vec![0usize;lists.len()]; let mut out=Vec::new();
+    'outer: while idx[0]<lists[0].len(){
+        let doc=lists[0][idx[0]].0;
+        for (k,l) in lists.iter().enumerate().skip(1){
+            while idx[k]<l.len() && l[idx[k]].0<doc { idx[k]+=1; }
+            if idx[k]>=l.len(){ break 'outer; }
+            if l[idx[k]].0>doc { let next=l[idx[k]].0; while idx[0]<lists[0].len() && lists[0][idx[0]].0<next { idx[0]+=1; } continue 'outer; }
+        }
+        out.push((doc, lists.iter().zip(&idx).map(|(l,&i)| l[i].1.clone()).collect()));
+        idx[0]+=1;
+    }
+    out
+}
+
+/// One position per slot with `p - shift[i]` in `[lo, hi]`, slots of the
+/// same term taking distinct positions; `None` if no such assignment.
+/// Greedy in slot order is exact: a term's slots have nondecreasing shifts,
+/// so their ranges are ordered and the smallest free position is never worse.
+fn assign(lists:&[Vec<u32>], same:&[usize], shift:&[i64], lo:i64, hi:i64)->Option<Vec<u32>>{
+    let mut last:Vec<Option<u32>>=vec![None;lists.len()]; let mut out=Vec::with_capacity(lists.len());
+    for (i,ps) in lists.iter().enumerate(){
+        let from=(lo+shift[i]).max(last[same[i]].map_or(0,|p| p as i64+1));
+        let j=ps.partition_point(|&p| (p as i64)<from);
+        let p=*ps.get(j).filter(|&&p| p as i64<=hi+shift[i])?;
+        last[same[i]]=Some(p); out.push(p);
+    }
+    Some(out)
+}
+
+/// Minimal key ranges `[lo, hi]` with `hi - lo <= max_range` that hold an
+/// `assign`ment for every slot, with that assignment.
+fn covering_windows(terms:&[&str], lists:&[Vec<u32>], shift:&[i64], max_range:i64)->Vec<(i64,i64,Vec<u32>)>{
+    let same:Vec<usize>=terms.iter().map(|t| terms.iter().position(|u| u==t).unwrap()).collect();
+    let mut keys:Vec<i64>=lists.iter().zip(shift).flat_map(|(ps,&s)| ps.iter().map(move |&p| p as i64-s)).collect();
+    keys.sort_unstable(); keys.dedup();
+    let valid=|l:usize,h:usize| assign(lists,&same,shift,keys[l],keys[h]);
+    let mut lo=0usize; let mut out=Vec::new();
+    for hi in 0..keys.len(){
+        if valid(lo,hi).is_none(){ continue; }
+        while lo<hi && valid(lo+1,hi).is_some() { lo+=1; }
+        if keys[hi]-keys[lo]<=max_range { out.push((keys[lo],keys[hi],valid(lo,hi).unwrap())); }
+        lo+=1;
+    }
+    out
+}
+
+/// Phrase with slop: each term's position minus its index in the phrase
+/// must span at most `slop` (0 = exact phrase, reorderings cost extra).
+/// A repeated term needs a distinct occurrence for each of its slots.
+pub fn sloppy_phrase(inv:&InvertedIndex, terms:&[&str], slop:u32)->Vec<(u32,Vec<Window>)>{
+    let shift:Vec<i64>=(0..terms.len() as i64).collect(); let mut out=Vec::new();
+    for (doc,lists) in conjunctive_postings(inv,terms){
+        let wins:Vec<Window>=covering_windows(terms,&lists,&shift,slop as i64).into_iter().map(|(lo,hi,ps)| {
+            Window{ start:*ps.iter().min().unwrap(), end:*ps.iter().max().unwrap(), cost:(hi-lo) as u32 }
+        }).collect();
+        if !wins.is_empty(){ out.push((doc,wins)); }
+    }
+    out
+}
+
+/// All terms within a window with at most `k` intervening tokens; with
+/// `ordered` the terms must also appear in query order. Each term takes
+/// its own occurrence, so a repeated term must occur that many times.
+pub fn near_query(inv:&InvertedIndex, terms:&[&str], k:u32, ordered:bool)->Vec<(u32,Vec<Window>)>{
+    let n=terms.len() as i64; let mut out=Vec::new();
+    for (doc,lists) in conjunctive_postings(inv,terms){
+        let mut wins:Vec<Window>=Vec::new();
+        if ordered {
+            // earliest completion for each start occurrence of the first term
+            for &p0 in &lists[0] {
+                let mut cur=p0; let mut ok=true;
+                for ps in &lists[1..] { match ps.iter().find(|&&p| p>cur) { Some(&p)=>cur=p, None=>{ ok=false; break; } } }
+                let cost=(cur-p0+1) as i64-n;
+                if !ok || cost>k as i64 { continue; }
+                let w=Window{ start:p0, end:cur, cost:cost as u32 };
+                // a later start with the same end is the tighter window
+                match wins.last_mut() { Some(last) if last.end==w.end => *last=w, _ => wins.push(w) }
+            }
+        } else {
+            for (s,e,_) in covering_windows(terms,&lists,&vec![0;terms.len()],k as i64+n-1){
+                let cost=u32::try_from(e-s+1-n).expect("window holds a distinct position per term");
+                wins.push(Window{ start:s as u32, end:e as u32, cost });
+            }
+        }
+        if !wins.is_empty(){ out.push((doc,wins)); }
+    }
+    out
+}
+#[cfg(test)] mod tests{ use super::*; use crate::index::inverted::InvBuilder; #[test] fn adjacent_tokens(){ let mut b=InvBuilder::default(); b.add_doc(0,"Neural networks are cool."); b.add_doc(1,"networks, neural ones"); let inv=b.finalize(); assert_eq!(phrase_query(&inv,&["neural","networks"]),vec![0]); }
+    #[test] fn slop_and_near(){ let mut b=InvBuilder::default(); b.add_doc(0,"neural network training"); b.add_doc(1,"training of a big neural net"); let inv=b.finalize();
+        assert!(sloppy_phrase(&inv,&["neural","training"],0).is_empty());
+        assert_eq!(sloppy_phrase(&inv,&["neural","training"],1),vec![(0,vec![Window{start:0,end:2,cost:1}])]);
+        assert_eq!(sloppy_phrase(&inv,&["neural","training"],5).len(),2);
+        assert_eq!(near_query(&inv,&["training","neural"],3,false),vec![(0,vec![Window{start:0,end:2,cost:1}]),(1,vec![Window{start:0,end:4,cost:3}])]);
+        assert_eq!(near_query(&inv,&["training","neural"],3,true),vec![(1,vec![Window{start:0,end:4,cost:3}])]); }
+    #[test] fn repeated_terms_take_distinct_positions(){ let mut b=InvBuilder::default(); b.add_doc(0,"apple banana"); b.add_doc(1,"the cat the dog"); b.add_doc(2,"the end"); let inv=b.finalize();
+        assert!(near_query(&inv,&["apple","apple"],2,false).is_empty());
+        assert!(near_query(&inv,&["apple","apple"],2,true).is_empty());
+        assert!(sloppy_phrase(&inv,&["apple","apple"],1).is_empty());
+        assert!(sloppy_phrase(&inv,&["the","the"],0).is_empty());
+        assert_eq!(sloppy_phrase(&inv,&["the","the"],1),vec![(1,vec![Window{start:0,end:2,cost:1}])]);
+        assert_eq!(near_query(&inv,&["the","the"],1,false),vec![(1,vec![Window{start:0,end:2,cost:1}])]);
+        assert_eq!(near_query(&inv,&["the","cat","the"],0,false),vec![(1,vec![Window{start:0,end:2,cost:0}])]); } }
@@ -1 +1,91 @@
-// TODO
+use crate::index::fmindex::FMIndex;
+
+use crate::index::doctable::GAP_BYTES;
+pub use crate::index::doctable::DocTable;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DocMatch {
+    pub doc: u32,
+    pub count: u32,
+}
+
+/// Up to `limit` occurrences of `pat` as `(doc_id, offset_in_doc)`, sorted.
+/// Rows are located in suffix order until `limit` of them resolve, so with
+/// more matches than that these are some `limit` of them, not the first.
+pub fn substring_locate(fm: &FMIndex, docs: &DocTable, pat: &[u8], limit: usize) -> Vec<(u32, usize)> {
+    let Some(range) = fm.backward_search(pat) else { return vec![] };
+    let mut locs: Vec<(u32, usize)> = (range.l..range.r)
+        .filter_map(|r| docs.resolve(fm.locate(r), pat.len()))
+        .take(limit)
+        .collect();
+    locs.sort_unstable();
+    locs
+}
+
+/// Occurrences of `pat` inside documents. Unless `pat` holds a byte that can
+/// lie between documents (see `GAP_BYTES`), that is the size of its row
+/// range and nothing is located; the empty pattern matches every row.
+pub fn substring_count(fm: &FMIndex, docs: &DocTable, pat: &[u8]) -> usize {
+    let Some(range) = fm.backward_search(pat) else { return 0 };
+    if !pat.iter().any(|b| GAP_BYTES.contains(b)) {
+        return (range.r - range.l) as usize;
+    }
+    (range.l..range.r).filter(|&r| docs.resolve(fm.locate(r), pat.len()).is_some()).count()
+}
+
+/// Distinct documents containing `pat`, with occurrence counts, by doc id.
+/// Every occurrence is located; use `substring_count` for a total.
+pub fn substring_docs(fm: &FMIndex, docs: &DocTable, pat: &[u8]) -> Vec<DocMatch> {
+    let Some(range) = fm.backward_search(pat) else { return vec![] };
+    let mut ids: Vec<u32> = (range.l..range.r).filter_map(|r| docs.resolve(fm.locate(r), pat.len()).map(|(d, _)| d)).collect();
+    ids.sort_unstable();
+    let mut out: Vec<DocMatch> = Vec::new();
+    for doc in ids {
+        match out.last_mut() {
+            Some(m) if m.doc == doc => m.count += 1,
+            _ => out.push(DocMatch { doc, count: 1 }),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::doctable::CollectionWriter;
+    use crate::index::suffix_array::build_sa;
+
+    #[test]
+    fn docs_and_counts() {
+        let text = b"the cat\ncatcat\r\ndog\n";
+        let docs = DocTable::from_lines(text);
+        assert_eq!(docs.len(), 3);
+        assert_eq!(docs.resolve(9, 3), Some((1, 1)));
+        assert_eq!(docs.resolve(6, 3), None); // "t\nc" crosses a separator
+
+        let fm = FMIndex::build(text, &build_sa(text), 4);
+        assert_eq!(substring_docs(&fm, &docs, b"cat"), vec![
+            DocMatch { doc: 0, count: 1 },
+            DocMatch { doc: 1, count: 2 },
+        ]);
+        assert_eq!(substring_locate(&fm, &docs, b"cat", 9), vec![(0, 4), (1, 0), (1, 3)]);
+        let two = substring_locate(&fm, &docs, b"cat", 2);
+        assert!(two.len() == 2 && two.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(substring_count(&fm, &docs, b"cat"), 3);
+        assert_eq!(substring_count(&fm, &docs, b"t\nc"), 0);
+        assert_eq!(substring_count(&fm, &docs, b""), fm.full_range().r as usize);
+    }
+
+    #[test]
+    fn limit_counts_resolved_matches() {
+        // the separator's "\n" sorts before the one inside doc 0
+        let mut c = CollectionWriter::new(Vec::new());
+        c.add("a", b"x\nz").unwrap();
+        c.add("b", b"y").unwrap();
+        let (text, docs) = c.finish();
+        let fm = FMIndex::build(&text, &build_sa(&text), 4);
+        assert_eq!(substring_locate(&fm, &docs, b"\n", 1), vec![(0, 1)]);
+        assert_eq!(substring_count(&fm, &docs, b"\n"), 1);
+        assert_eq!(substring_docs(&fm, &docs, b"\n"), vec![DocMatch { doc: 0, count: 1 }]);
+    }
+}
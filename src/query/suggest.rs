@@ -0,0 +1,137 @@
+use crate::index::inverted::InvertedIndex;
+use crate::query::fuzzy::fuzzy_expand;
+
+/// Most candidates collected per term before ranking. Candidates are
+/// gathered one edit distance at a time; a distance with more terms than
+/// this stops its dictionary walk early and only the closer ones are ranked,
+/// so short terms do not walk half the dictionary.
+const MAX_CANDIDATES: usize = 256;
+
+/// A present term is only replaced if a neighbour is this many times more
+/// frequent (so rare-but-correct spellings survive).
+const REPLACE_RATIO: u32 = 10;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Suggestion {
+    pub term: String,
+    pub distance: u32,
+    pub df: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryCorrection {
+    pub terms: Vec<String>,
+    pub distance: u32,
+}
+
+impl QueryCorrection {
+    pub fn text(&self) -> String { self.terms.join(" ") }
+}
+
+fn df(inv: &InvertedIndex, term: &str) -> u32 { inv.postings(term).len() as u32 }
+
+/// Index terms within `max_edits` of `term` (the term itself included when
+/// indexed), best first: fewer edits, then higher document frequency.
+pub fn suggest_term(inv: &InvertedIndex, term: &str, max_edits: u32, n: usize) -> Vec<Suggestion> {
+    let mut cands = Vec::new();
+    for k in 0..=max_edits {
+        // errors once the walk passes MAX_CANDIDATES matches
+        match fuzzy_expand(inv, term, k, MAX_CANDIDATES) {
+            Ok(c) => cands = c,
+            Err(_) => break,
+        }
+    }
+    let mut out: Vec<Suggestion> =
+        cands.into_iter().map(|(t, distance)| Suggestion { df: df(inv, &t), term: t, distance }).collect();
+    out.sort_by(|a, b| a.distance.cmp(&b.distance).then(b.df.cmp(&a.df)).then(a.term.cmp(&b.term)));
+    out.truncate(n);
+    out
+}
+
+/// Per-term options for whole-query correction: indexed terms keep
+/// themselves first and only compete with much more frequent neighbours.
+fn term_options(inv: &InvertedIndex, term: &str, max_edits: u32, beam: usize) -> Vec<Suggestion> {
+    let mut opts = suggest_term(inv, term, max_edits, beam + 1);
+    if let Some(own) = opts.iter().position(|s| s.distance == 0) {
+        let own_df = opts[own].df;
+        opts.retain(|s| s.distance == 0 || s.df >= own_df.saturating_mul(REPLACE_RATIO));
+    }
+    if opts.is_empty() {
+        // nothing close enough: keep the term so the rest can still be fixed
+        opts.push(Suggestion { term: term.to_string(), distance: 0, df: 0 });
+    }
+    opts.truncate(beam);
+    opts
+}
+
+/// Up to `n` corrected versions of a multi-term query, best first (fewer
+/// total edits, then more frequent terms). Built with a beam search over
+/// per-term candidates; the unchanged query is never returned.
+pub fn suggest_query(inv: &InvertedIndex, terms: &[String], max_edits: u32, n: usize) -> Vec<QueryCorrection> {
+    let beam = n.max(1) + 2;
+    // (total distance, sum of ln(df+1), terms)
+    let mut partial: Vec<(u32, f32, Vec<String>)> = vec![(0, 0.0, Vec::new())];
+    for term in terms {
+        let opts = term_options(inv, term, max_edits, beam);
+        let mut next = Vec::with_capacity(partial.len() * opts.len());
+        for (d, w, ts) in &partial {
+            for o in &opts {
+                let mut ts = ts.clone();
+                ts.push(o.term.clone());
+                next.push((d + o.distance, w + (o.df as f32 + 1.0).ln(), ts));
+            }
+        }
+        next.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal)));
+        next.truncate(beam);
+        partial = next;
+    }
+    partial
+        .into_iter()
+        .filter(|(_, _, ts)| ts != terms)
+        .take(n)
+        .map(|(distance, _, terms)| QueryCorrection { terms, distance })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::inverted::InvBuilder;
+
+    #[test]
+    fn did_you_mean() {
+        let mut b = InvBuilder::default();
+        b.add_doc(0, "neural networks learn");
+        b.add_doc(1, "neural network training");
+        b.add_doc(2, "a neural net");
+        let inv = b.finalize();
+
+        let s = suggest_term(&inv, "nerual", 2, 3);
+        assert_eq!(s[0], Suggestion { term: "neural".into(), distance: 2, df: 3 });
+
+        let q: Vec<String> = vec!["nerual".into(), "netwrk".into()];
+        let fixes = suggest_query(&inv, &q, 2, 2);
+        assert_eq!(fixes[0].text(), "neural network");
+        assert_eq!(fixes[0].distance, 3);
+        assert!(suggest_query(&inv, &["neural".to_string()], 2, 3).is_empty());
+    }
+
+    #[test]
+    fn crowded_neighbourhood_keeps_the_closest() {
+        // 312 terms "mm[a-l][a-z]" sort before "mmmz"; most are 2 edits from "mmmm"
+        let mut b = InvBuilder::default();
+        let mut doc = 0;
+        for c3 in 'a'..='l' {
+            for c4 in 'a'..='z' {
+                b.add_doc(doc, &format!("mm{c3}{c4}"));
+                doc += 1;
+            }
+        }
+        b.add_doc(doc, "mmmz");
+        b.add_doc(doc + 1, "mmmz");
+        let inv = b.finalize();
+        let s = suggest_term(&inv, "mmmm", 2, 3);
+        assert_eq!(s[0], Suggestion { term: "mmmz".into(), distance: 1, df: 2 });
+        assert!(s.iter().all(|s| s.distance == 1));
+    }
+}
@@ -0,0 +1,289 @@
+use crate::index::trigram::{trigram, Trigram, TrigramIndex};
+use anyhow::*;
+use regex_automata::meta;
+use regex_syntax::hir::{Class, Hir, HirKind};
+use std::collections::BTreeSet;
+
+/// Largest string set tracked exactly before falling back to trigrams.
+const MAX_SET: usize = 64;
+/// Classes with more members than this are treated as "any character".
+const MAX_CLASS: usize = 16;
+
+/// Boolean query over document trigrams: a document can only match the
+/// regex if it satisfies the query.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrigramQuery {
+    All,
+    None,
+    Tri(Trigram),
+    And(Vec<TrigramQuery>),
+    Or(Vec<TrigramQuery>),
+}
+
+impl TrigramQuery {
+    fn and(self, other: Self) -> Self {
+        use TrigramQuery::*;
+        match (self, other) {
+            (None, _) | (_, None) => None,
+            (All, q) | (q, All) => q,
+            (And(mut a), And(b)) => { for q in b { if !a.contains(&q) { a.push(q); } } And(a) }
+            (And(mut a), q) | (q, And(mut a)) => { if !a.contains(&q) { a.push(q); } And(a) }
+            (a, b) if a == b => a,
+            (a, b) => And(vec![a, b]),
+        }
+    }
+
+    fn or(self, other: Self) -> Self {
+        use TrigramQuery::*;
+        match (self, other) {
+            (All, _) | (_, All) => All,
+            (None, q) | (q, None) => q,
+            (Or(mut a), Or(b)) => { for q in b { if !a.contains(&q) { a.push(q); } } Or(a) }
+            (Or(mut a), q) | (q, Or(mut a)) => { if !a.contains(&q) { a.push(q); } Or(a) }
+            (a, b) if a == b => a,
+            (a, b) => Or(vec![a, b]),
+        }
+    }
+
+    /// Sorted ids of the documents satisfying the query.
+    pub fn eval(&self, idx: &TrigramIndex) -> Vec<u32> {
+        match self {
+            TrigramQuery::All => (0..idx.docs().len() as u32).collect(),
+            TrigramQuery::None => vec![],
+            TrigramQuery::Tri(t) => idx.postings(*t),
+            TrigramQuery::And(qs) => {
+                let mut acc: Option<Vec<u32>> = None;
+                for q in qs {
+                    let docs = q.eval(idx);
+                    let next = match acc { None => docs, Some(a) => a.into_iter().filter(|d| docs.binary_search(d).is_ok()).collect() };
+                    if next.is_empty() { return next; }
+                    acc = Some(next);
+                }
+                acc.unwrap_or_default()
+            }
+            TrigramQuery::Or(qs) => {
+                let mut out: Vec<u32> = qs.iter().flat_map(|q| q.eval(idx)).collect();
+                out.sort_unstable();
+                out.dedup();
+                out
+            }
+        }
+    }
+}
+
+type Set = BTreeSet<Vec<u8>>;
+
+/// What is known about the strings a sub-expression matches (Cox, "Regular
+/// Expression Matching with a Trigram Index"): the exact set when small,
+/// otherwise possible prefixes and suffixes plus a trigram query.
+#[derive(Clone)]
+struct Info {
+    empty: bool,
+    exact: Option<Set>,
+    prefix: Set,
+    suffix: Set,
+    query: TrigramQuery,
+}
+
+fn set_of(strings: impl IntoIterator<Item = Vec<u8>>) -> Set { strings.into_iter().collect() }
+
+fn cross(a: &Set, b: &Set) -> Set {
+    a.iter().flat_map(|x| b.iter().map(move |y| [x.as_slice(), y].concat())).collect()
+}
+
+/// Any string in `set` occurs in the document: OR over strings of the AND
+/// of their trigrams (a string shorter than three bytes says nothing).
+fn trigrams_of(set: &Set) -> TrigramQuery {
+    set.iter().fold(TrigramQuery::None, |acc, s| {
+        let q = if s.len() < 3 {
+            TrigramQuery::All
+        } else {
+            s.windows(3).fold(TrigramQuery::All, |q, t| q.and(TrigramQuery::Tri(trigram(t))))
+        };
+        acc.or(q)
+    })
+}
+
+impl Info {
+    fn exact(set: Set) -> Self {
+        Info { empty: set.contains(&b""[..]), exact: Some(set), prefix: Set::new(), suffix: Set::new(), query: TrigramQuery::All }
+    }
+
+    fn any(empty: bool) -> Self {
+        let blank = set_of([vec![]]);
+        Info { empty, exact: None, prefix: blank.clone(), suffix: blank, query: TrigramQuery::All }
+    }
+
+    fn prefixes(&self) -> &Set { self.exact.as_ref().unwrap_or(&self.prefix) }
+    fn suffixes(&self) -> &Set { self.exact.as_ref().unwrap_or(&self.suffix) }
+
+    /// Trade the exact set for prefixes, suffixes and its trigrams.
+    fn inexact(mut self) -> Self {
+        if let Some(set) = self.exact.take() {
+            self.query = self.query.and(trigrams_of(&set));
+            self.prefix = set.clone();
+            self.suffix = set;
+        }
+        self.simplify()
+    }
+
+    /// Keep sets bounded: fold long prefixes and suffixes into the query and
+    /// keep only their outer two bytes, shortening further if still too many.
+    fn simplify(mut self) -> Self {
+        if self.exact.as_ref().is_some_and(|s| s.len() > MAX_SET) { return self.inexact(); }
+        if self.exact.is_some() { return self; }
+        self.query = self.query.and(trigrams_of(&self.prefix)).and(trigrams_of(&self.suffix));
+        for keep in [2, 1, 0] {
+            self.prefix = self.prefix.iter().map(|s| s[..s.len().min(keep)].to_vec()).collect();
+            if self.prefix.len() <= MAX_SET { break; }
+        }
+        for keep in [2, 1, 0] {
+            self.suffix = self.suffix.iter().map(|s| s[s.len() - s.len().min(keep)..].to_vec()).collect();
+            if self.suffix.len() <= MAX_SET { break; }
+        }
+        self
+    }
+
+    fn concat(self, y: Info) -> Info {
+        if let (Some(a), Some(b)) = (&self.exact, &y.exact) {
+            if a.len() * b.len() <= MAX_SET { return Info::exact(cross(a, b)); }
+            return self.inexact().concat(y.inexact());
+        }
+        let query = self.query.clone().and(y.query.clone()).and(trigrams_of(&cross(self.suffixes(), y.prefixes())));
+        let prefix = match (&self.exact, self.empty) {
+            (Some(a), _) => cross(a, y.prefixes()),
+            (None, true) => self.prefix.union(y.prefixes()).cloned().collect(),
+            (None, false) => self.prefix.clone(),
+        };
+        let suffix = match (&y.exact, y.empty) {
+            (Some(b), _) => cross(self.suffixes(), b),
+            (None, true) => y.suffix.union(self.suffixes()).cloned().collect(),
+            (None, false) => y.suffix.clone(),
+        };
+        Info { empty: self.empty && y.empty, exact: None, prefix, suffix, query }.simplify()
+    }
+
+    fn alternate(self, y: Info) -> Info {
+        if let (Some(a), Some(b)) = (&self.exact, &y.exact) {
+            return Info::exact(a.union(b).cloned().collect()).simplify();
+        }
+        let (x, y) = (self.inexact(), y.inexact());
+        Info {
+            empty: x.empty || y.empty,
+            exact: None,
+            prefix: x.prefix.union(&y.prefix).cloned().collect(),
+            suffix: x.suffix.union(&y.suffix).cloned().collect(),
+            query: x.query.or(y.query),
+        }
+        .simplify()
+    }
+}
+
+fn class_strings(class: &Class) -> Option<Set> {
+    match class {
+        Class::Bytes(c) => {
+            let n: usize = c.iter().map(|r| (r.end() - r.start()) as usize + 1).sum();
+            (n <= MAX_CLASS).then(|| c.iter().flat_map(|r| r.start()..=r.end()).map(|b| vec![b]).collect())
+        }
+        Class::Unicode(c) => {
+            let n: usize = c.iter().map(|r| (r.end() as usize) - (r.start() as usize) + 1).sum();
+            (n <= MAX_CLASS).then(|| {
+                c.iter().flat_map(|r| r.start()..=r.end()).map(|ch| ch.to_string().into_bytes()).collect()
+            })
+        }
+    }
+}
+
+fn analyze(hir: &Hir) -> Info {
+    match hir.kind() {
+        HirKind::Empty | HirKind::Look(_) => Info::exact(set_of([vec![]])),
+        HirKind::Literal(lit) => Info::exact(set_of([lit.0.to_vec()])),
+        HirKind::Class(c) => class_strings(c).map_or_else(|| Info::any(false), Info::exact),
+        HirKind::Capture(cap) => analyze(&cap.sub),
+        HirKind::Concat(subs) => subs.iter().fold(Info::exact(set_of([vec![]])), |acc, h| acc.concat(analyze(h))),
+        HirKind::Alternation(subs) => subs.iter().map(analyze).reduce(Info::alternate).unwrap_or_else(|| Info::exact(Set::new())),
+        HirKind::Repetition(rep) => {
+            let sub = analyze(&rep.sub);
+            if rep.min == 0 && rep.max == Some(1) { return sub.alternate(Info::exact(set_of([vec![]]))); }
+            if rep.min == 0 { return Info::any(true); }
+            // x{n,m} contains x^n and starts and ends like it; more than three
+            // copies add nothing worth tracking
+            let mut info = sub.clone();
+            for _ in 1..rep.min.min(3) { info = info.concat(sub.clone()); }
+            if rep.max != Some(rep.min) || rep.min > 3 { info = info.inexact(); }
+            info
+        }
+    }
+}
+
+/// The trigram query any document matching `hir` satisfies.
+pub fn compile(hir: &Hir) -> TrigramQuery { analyze(hir).inexact().query }
+
+/// A regex plus the trigram query used to prefilter documents.
+pub struct TrigramRegex {
+    re: meta::Regex,
+    query: TrigramQuery,
+}
+
+/// One confirmed match, as a byte range inside a document.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrigramMatch {
+    pub doc: u32,
+    pub off: usize,
+    pub len: usize,
+}
+
+impl TrigramRegex {
+    pub fn new(pattern: &str) -> Result<Self> {
+        let hir = regex_syntax::Parser::new().parse(pattern)?;
+        Ok(Self { query: compile(&hir), re: meta::Regex::new(pattern)? })
+    }
+
+    pub fn query(&self) -> &TrigramQuery { &self.query }
+
+    /// Documents that may match, by id.
+    pub fn candidates(&self, idx: &TrigramIndex) -> Vec<u32> { self.query.eval(idx) }
+
+    /// Up to `limit` matches, by doc then offset, confirmed on the stored text.
+    pub fn search(&self, idx: &TrigramIndex, limit: usize) -> Vec<TrigramMatch> {
+        let mut out = Vec::new();
+        for doc in self.candidates(idx) {
+            let Some(text) = idx.doc_text(doc) else { continue };
+            for m in self.re.find_iter(text) {
+                if out.len() == limit { return out; }
+                out.push(TrigramMatch { doc, off: m.start(), len: m.len() });
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::trigram::TrigramBuilder;
+
+    #[test]
+    fn prefilter_keeps_every_match() {
+        let docs = ["hello world", "help wanted", "yellow fellow", "abcdef", "xyzdef", "ABC def", "héllo wörld", "aaa"];
+        let mut b = TrigramBuilder::default();
+        for (i, d) in docs.iter().enumerate() { b.add(&i.to_string(), d.as_bytes()).unwrap(); }
+        let idx = b.finalize();
+
+        let tri = |s: &[u8]| TrigramQuery::Tri(trigram(s));
+        assert_eq!(TrigramRegex::new("hello").unwrap().query(), &TrigramQuery::And(vec![tri(b"hel"), tri(b"ell"), tri(b"llo")]));
+        assert_eq!(TrigramRegex::new("a.*").unwrap().query(), &TrigramQuery::All);
+        assert_eq!(TrigramRegex::new("[ab]").unwrap().query(), &TrigramQuery::All);
+        assert_eq!(TrigramRegex::new("(abc|xyz)def").unwrap().candidates(&idx), vec![3, 4]);
+        assert_eq!(TrigramRegex::new("yel+ow").unwrap().candidates(&idx), vec![2]);
+
+        for pat in ["hello", "hel+o?", "el+o", "(abc|xyz)def", "(?i)abc", "w[oö]rld", "[a-z]+ow", "x?y?zd.f", "a{3}", "e.*l.*w", "(?m)^h", "q"] {
+            let re = TrigramRegex::new(pat).unwrap();
+            let naive = meta::Regex::new(pat).unwrap();
+            let expect: Vec<TrigramMatch> = docs.iter().enumerate()
+                .flat_map(|(d, t)| naive.find_iter(t.as_bytes()).map(move |m| TrigramMatch { doc: d as u32, off: m.start(), len: m.len() }))
+                .collect();
+            assert_eq!(re.search(&idx, usize::MAX), expect, "{pat}");
+        }
+    }
+}
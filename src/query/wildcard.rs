@@ -0,0 +1,99 @@
+use crate::index::inverted::InvertedIndex;
+use anyhow::*;
+use std::ops::Bound;
+
+/// Default cap on how many dictionary terms one pattern may expand to.
+pub const DEFAULT_MAX_EXPANSIONS: usize = 1024;
+
+pub fn is_wildcard(word: &str) -> bool {
+    word.contains(['*', '?'])
+}
+
+/// Glob match on chars: `?` is exactly one char, `*` any run (incl. empty).
+pub fn wildcard_match(pat: &str, s: &str) -> bool {
+    let p: Vec<char> = pat.chars().collect();
+    let t: Vec<char> = s.chars().collect();
+    let (mut i, mut j) = (0, 0);
+    let mut star: Option<(usize, usize)> = None; // (pattern idx after '*', text idx)
+    while j < t.len() {
+        if i < p.len() && (p[i] == '?' || p[i] == t[j]) {
+            i += 1;
+            j += 1;
+        } else if i < p.len() && p[i] == '*' {
+            star = Some((i + 1, j));
+            i += 1;
+        } else if let Some((si, sj)) = star {
+            i = si;
+            j = sj + 1;
+            star = Some((si, sj + 1));
+        } else {
+            return false;
+        }
+    }
+    p[i..].iter().all(|&c| c == '*')
+}
+
+/// Dictionary terms starting with `prefix`, via a range scan of the sorted
+/// dictionary. Errors if more than `max` terms match.
+pub fn expand_prefix(inv: &InvertedIndex, prefix: &str, max: usize) -> Result<Vec<String>> {
+    expand_wildcard(inv, &format!("{prefix}*"), max)
+}
+
+/// Dictionary terms matching a `?`/`*` pattern. Only the range sharing the
+/// pattern's literal prefix is scanned, so `neur*` never touches other terms.
+pub fn expand_wildcard(inv: &InvertedIndex, pattern: &str, max: usize) -> Result<Vec<String>> {
+    let lit = pattern.find(['*', '?']).map_or(pattern, |i| &pattern[..i]);
+    let mut out = Vec::new();
+    let from = (Bound::Included(lit), Bound::Unbounded);
+    for term in inv.dict.range::<str, _>(from).map(|(t, _)| t).take_while(|t| t.starts_with(lit)) {
+        if wildcard_match(pattern, term) {
+            ensure!(out.len() < max, "pattern {pattern:?} expands to more than {max} terms");
+            out.push(term.clone());
+        }
+    }
+    Ok(out)
+}
+
+/// Union of the postings of `terms`: per doc, the merged sorted positions.
+pub fn merged_postings(inv: &InvertedIndex, terms: &[String]) -> Vec<(u32, Vec<u32>)> {
+    let mut by_doc: std::collections::BTreeMap<u32, Vec<u32>> = Default::default();
+    for t in terms {
+        for (doc, ps) in inv.postings(t) {
+            by_doc.entry(doc).or_default().extend(ps);
+        }
+    }
+    by_doc
+        .into_iter()
+        .map(|(doc, mut ps)| {
+            ps.sort_unstable();
+            (doc, ps)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::inverted::InvBuilder;
+
+    #[test]
+    fn glob() {
+        assert!(wildcard_match("neur*", "neural"));
+        assert!(wildcard_match("n?t*k", "network"));
+        assert!(wildcard_match("*ing", "training"));
+        assert!(!wildcard_match("n?t", "neat"));
+        assert!(!wildcard_match("ab*c", "abcd"));
+    }
+
+    #[test]
+    fn expand() {
+        let mut b = InvBuilder::default();
+        b.add_doc(0, "neural networks are neat");
+        b.add_doc(1, "a network of neurons");
+        let inv = b.finalize();
+        assert_eq!(expand_prefix(&inv, "neur", 10).unwrap(), vec!["neural", "neurons"]);
+        assert_eq!(expand_wildcard(&inv, "ne*t", 10).unwrap(), vec!["neat"]);
+        assert!(expand_wildcard(&inv, "ne*", 3).is_err());
+        assert_eq!(merged_postings(&inv, &["neural".into(), "neurons".into()]), vec![(0, vec![0]), (1, vec![3])]);
+    }
+}
@@ -1,35 +1,204 @@
+use crate::index::inverted::{InvBuilder, InvertedIndex};
+use crate::rank::similarity::{take_top, CollectionStats, DocStats, Hit, Similarity, TermStats};
+use std::collections::HashMap;
+
+/// Okapi BM25 with Lucene's IDF, `ln(1 + (N - df + 0.5) / (df + 0.5))`, which
+/// stays positive for terms in more than half of the documents.
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct BM25 {
     pub k1: f32,
     pub b: f32,
-    pub avgdl: f32,
+}
+
+impl Default for BM25 {
+    fn default() -> Self { Self { k1: 1.2, b: 0.75 } }
 }
 
 impl BM25 {
-    pub fn new(k1: f32, b: f32, avgdl: f32) -> Self {
-        Self { k1, b, avgdl }
+    pub fn new(k1: f32, b: f32) -> Self {
+        Self { k1, b }
+    }
+
+    pub fn idf(df: f32, n_docs: f32) -> f32 {
+        (1.0 + (n_docs - df + 0.5) / (df + 0.5)).ln()
+    }
+
+    /// Length-normalized term frequency saturation, `tf (k1 + 1) / (tf + K)`.
+    pub fn tf_norm(&self, tf: f32, dl: f32, avgdl: f32) -> f32 {
+        tf * (self.k1 + 1.0) / (tf + self.k1 * (1.0 - self.b + self.b * dl / avgdl))
+    }
+}
+
+impl Similarity for BM25 {
+    fn score(&self, tf: f32, term: &TermStats, doc: &DocStats, coll: &CollectionStats) -> f32 {
+        Self::idf(term.df, coll.n_docs as f32) * self.tf_norm(tf, doc.len, coll.avgdl)
+    }
+}
+
+/// BM25+ (Lv & Zhai 2011): adds `delta` to every matching term's saturated
+/// tf, so a long document containing a term always beats one without it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BM25Plus {
+    pub bm25: BM25,
+    pub delta: f32,
+}
+
+impl Default for BM25Plus {
+    fn default() -> Self { Self { bm25: BM25::default(), delta: 1.0 } }
+}
+
+impl Similarity for BM25Plus {
+    fn score(&self, tf: f32, term: &TermStats, doc: &DocStats, coll: &CollectionStats) -> f32 {
+        BM25::idf(term.df, coll.n_docs as f32) * (self.bm25.tf_norm(tf, doc.len, coll.avgdl) + self.delta)
+    }
+}
+
+/// BM25L (Lv & Zhai 2011): shifts the length-normalized tf by `delta` before
+/// saturation, so very long documents are not over-penalized.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BM25L {
+    pub bm25: BM25,
+    pub delta: f32,
+}
+
+impl Default for BM25L {
+    fn default() -> Self { Self { bm25: BM25::default(), delta: 0.5 } }
+}
+
+impl Similarity for BM25L {
+    fn score(&self, tf: f32, term: &TermStats, doc: &DocStats, coll: &CollectionStats) -> f32 {
+        let BM25 { k1, b } = self.bm25;
+        let ctd = tf / (1.0 - b + b * doc.len / coll.avgdl) + self.delta;
+        let idf = ((coll.n_docs as f32 + 1.0) / (term.df + 0.5)).ln();
+        idf * (k1 + 1.0) * ctd / (k1 + ctd)
+    }
+}
+
+/// Weight and length normalization of one BM25F field.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FieldParams {
+    pub weight: f32,
+    pub b: f32,
+}
+
+/// BM25F (Robertson et al. 2004): per-field tfs are length-normalized and
+/// weighted per field, summed, then saturated once with `k1`. Over a single
+/// field of weight 1 it equals BM25.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BM25F {
+    pub k1: f32,
+    pub fields: Vec<FieldParams>,
+}
+
+impl BM25F {
+    pub fn new(k1: f32, fields: Vec<FieldParams>) -> Self {
+        Self { k1, fields }
+    }
+
+    /// Combined tf of a term from its per-field tfs and field lengths.
+    pub fn pseudo_tf(&self, tfs: &[f32], lens: &[f32], avgdls: &[f32]) -> f32 {
+        self.fields.iter().zip(tfs).zip(lens.iter().zip(avgdls)).map(|((f, &tf), (&dl, &avgdl))| {
+            f.weight * tf / (1.0 - f.b + f.b * dl / avgdl)
+        }).sum()
+    }
+
+    fn saturate(&self, tf: f32, df: f32, n_docs: f32) -> f32 {
+        BM25::idf(df, n_docs) * tf * (self.k1 + 1.0) / (self.k1 + tf)
     }
 
-    pub fn score(&self, tf: f32, df: f32, n_docs: f32, dl: f32) -> f32 {
-        // ✅ IDF: always positive if term is informative
-        let idf = ((n_docs - df + 0.5) / (df + 0.5) + 1e-6).ln();
-let safe_df = if df > n_docs { n_docs - 1.0 } else { df };
-        // ✅ TF normalization
-        let norm = tf * (self.k1 + 1.0)
-            / (tf + self.k1 * (1.0 - self.b + self.b * dl / self.avgdl));
+    /// The `k` best documents for a query given as `(doc, tf)` postings per
+    /// term and per field (`terms[t][f]`), with one `CollectionStats` per
+    /// field. A term's df counts documents having it in any field.
+    pub fn top_k(&self, fields: &[CollectionStats], terms: &[Vec<Vec<(u32, f32)>>], k: usize) -> Vec<Hit> {
+        let n_docs = fields.iter().map(|f| f.n_docs).max().unwrap_or(0) as f32;
+        let avgdls: Vec<f32> = fields.iter().map(|f| f.avgdl).collect();
+        let mut scores: HashMap<u32, f32> = HashMap::new();
+        for per_field in terms {
+            let mut tfs: HashMap<u32, Vec<f32>> = HashMap::new();
+            for (f, postings) in per_field.iter().enumerate().take(fields.len()) {
+                for &(doc, tf) in postings {
+                    tfs.entry(doc).or_insert_with(|| vec![0.0; fields.len()])[f] += tf;
+                }
+            }
+            let df = tfs.len() as f32;
+            for (doc, tf) in tfs {
+                let lens: Vec<f32> = fields.iter().map(|f| f.doc_len(doc) as f32).collect();
+                *scores.entry(doc).or_insert(0.0) += self.saturate(self.pseudo_tf(&tf, &lens, &avgdls), df, n_docs);
+            }
+        }
+        take_top(scores.into_iter().map(|(doc, score)| Hit { doc, score }).collect(), k)
+    }
+}
 
-        idf * norm
+/// BM25F over the tab-separated fields of each line (missing fields are
+/// empty): one inverted index and `CollectionStats` per field.
+pub fn rank_fields(bm25f: &BM25F, docs: &[&str], terms: &[String], k: usize) -> Vec<Hit> {
+    let mut builders: Vec<InvBuilder> = (0..bm25f.fields.len()).map(|_| InvBuilder::default()).collect();
+    for (doc_id, line) in docs.iter().enumerate() {
+        for (b, text) in builders.iter_mut().zip(line.split('\t')) {
+            b.add_doc(doc_id as u32, text);
+        }
     }
+    let invs: Vec<InvertedIndex> = builders.into_iter().map(InvBuilder::finalize).collect();
+    let stats: Vec<CollectionStats> = invs.iter().map(|inv| CollectionStats::from_index(inv, Some(docs.len() as u32))).collect();
+    let postings: Vec<Vec<Vec<(u32, f32)>>> = terms
+        .iter()
+        .map(|t| invs.iter().map(|inv| inv.postings(t).into_iter().map(|(d, ps)| (d, ps.len() as f32)).collect()).collect())
+        .collect();
+    bm25f.top_k(&stats, &postings, k)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::rank::similarity::{similarity_by_name, top_k, TermPostings};
 
     #[test]
     fn test_bm25_positive() {
-        let bm25 = BM25::new(1.2, 0.75, 100.0);
-        let score = bm25.score(3.0, 10.0, 1000.0, 120.0);
+        let bm25 = BM25::new(1.2, 0.75);
+        let coll = CollectionStats { n_docs: 1000, doc_lens: vec![], total_len: 100_000, avgdl: 100.0, doc_norms: Default::default() };
+        let score = bm25.score(3.0, &TermStats { df: 10.0, cf: 30.0, qtf: 1.0 }, &DocStats { id: 0, len: 120.0 }, &coll);
         assert!(score > 0.0, "BM25 score should be positive, got {}", score);
+
+        // common terms still count, just less
+        let common = bm25.score(3.0, &TermStats { df: 900.0, cf: 2700.0, qtf: 1.0 }, &DocStats { id: 0, len: 120.0 }, &coll);
+        assert!(common > 0.0 && common < score, "got {common}");
     }
-}
 
+    #[test]
+    fn variants() {
+        let coll = CollectionStats { n_docs: 1000, doc_lens: vec![], total_len: 100_000, avgdl: 100.0, doc_norms: Default::default() };
+        let term = TermStats { df: 10.0, cf: 30.0, qtf: 1.0 };
+        let (short, long) = (DocStats { id: 0, len: 50.0 }, DocStats { id: 0, len: 5000.0 });
+        let bm25 = BM25::default();
+
+        // BM25+ keeps a floor of delta * idf for matching long docs
+        let plus = BM25Plus::default();
+        assert!(plus.score(1.0, &term, &long, &coll) >= BM25::idf(10.0, 1000.0));
+        assert!(plus.score(1.0, &term, &short, &coll) > bm25.score(1.0, &term, &short, &coll));
+
+        // BM25L penalizes the long doc less than BM25 does
+        let l = BM25L::default();
+        let ratio = |s: &dyn Similarity| s.score(1.0, &term, &long, &coll) / s.score(1.0, &term, &short, &coll);
+        assert!(ratio(&l) > ratio(&bm25));
+
+        // BM25F with one unit-weight field is BM25; title hits outweigh body hits
+        let f = BM25F::new(1.2, vec![FieldParams { weight: 1.0, b: 0.75 }]);
+        let one = CollectionStats::from_doc_lens(vec![2, 5, 9], None);
+        let postings = vec![(0, 1.0), (2, 3.0)];
+        let fielded = f.top_k(std::slice::from_ref(&one), &[vec![postings.clone()]], 10);
+        let plain = top_k(&bm25, &one, &[TermPostings::new(postings)], None, 10);
+        assert_eq!(fielded.len(), plain.len());
+        assert!(fielded.iter().zip(&plain).all(|(a, b)| a.doc == b.doc && (a.score - b.score).abs() < 1e-5));
+        let f = BM25F::new(1.2, vec![FieldParams { weight: 3.0, b: 0.75 }, FieldParams { weight: 1.0, b: 0.75 }]);
+        let title = CollectionStats::from_doc_lens(vec![2, 2, 2], None);
+        let body = CollectionStats::from_doc_lens(vec![10, 10, 10], None);
+        let terms = vec![vec![vec![(0, 1.0)], vec![(1, 1.0)]]];
+        assert_eq!(f.top_k(&[title, body], &terms, 10).iter().map(|h| h.doc).collect::<Vec<_>>(), vec![0, 1]);
+
+        let lines = ["rust\tgardening tips", "gardening\trust removal", "cooking"];
+        let hits = rank_fields(&f, &lines, &["rust".into()], 10);
+        assert_eq!(hits.iter().map(|h| h.doc).collect::<Vec<_>>(), vec![0, 1]);
+        assert!(similarity_by_name("bm25f", &Default::default()).is_err());
+    }
+}
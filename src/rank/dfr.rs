@@ -0,0 +1,88 @@
+use crate::rank::similarity::{CollectionStats, DocStats, Similarity, TermStats};
+use std::f32::consts::{E, PI};
+
+/// Term frequency normalization 2: `tf log2(1 + c avgdl / dl)`.
+fn tfn(tf: f32, c: f32, doc: &DocStats, coll: &CollectionStats) -> f32 {
+    tf * (1.0 + c * coll.avgdl / doc.len.max(1.0)).log2()
+}
+
+/// PL2 (Amati & van Rijsbergen 2002): Poisson randomness model, Laplace
+/// after-effect, normalization 2 with parameter `c`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PL2 {
+    pub c: f32,
+}
+
+impl Default for PL2 {
+    fn default() -> Self { Self { c: 1.0 } }
+}
+
+impl Similarity for PL2 {
+    fn score(&self, tf: f32, term: &TermStats, doc: &DocStats, coll: &CollectionStats) -> f32 {
+        let tfn = tfn(tf, self.c, doc, coll);
+        let lambda = term.cf / coll.n_docs.max(1) as f32;
+        if tfn <= 0.0 || lambda <= 0.0 { return 0.0; }
+        let info = tfn * (tfn / lambda).log2()
+            + (lambda + 1.0 / (12.0 * tfn) - tfn) * E.log2()
+            + 0.5 * (2.0 * PI * tfn).log2();
+        info / (tfn + 1.0)
+    }
+}
+
+/// InL2: inverse document frequency randomness model, Laplace after-effect,
+/// normalization 2 with parameter `c`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InL2 {
+    pub c: f32,
+}
+
+impl Default for InL2 {
+    fn default() -> Self { Self { c: 1.0 } }
+}
+
+impl Similarity for InL2 {
+    fn score(&self, tf: f32, term: &TermStats, doc: &DocStats, coll: &CollectionStats) -> f32 {
+        let tfn = tfn(tf, self.c, doc, coll);
+        tfn / (tfn + 1.0) * ((coll.n_docs as f32 + 1.0) / (term.df + 0.5)).log2()
+    }
+}
+
+/// DPH (Amati 2008): parameter-free hypergeometric model with Popper's
+/// normalization.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct DPH;
+
+impl Similarity for DPH {
+    fn score(&self, tf: f32, term: &TermStats, doc: &DocStats, coll: &CollectionStats) -> f32 {
+        let f = tf / doc.len.max(tf);
+        if f >= 1.0 || term.cf <= 0.0 { return 0.0; }
+        let norm = (1.0 - f) * (1.0 - f) / (tf + 1.0);
+        norm * (tf * (tf * coll.avgdl / doc.len * coll.n_docs as f32 / term.cf).log2() + 0.5 * (2.0 * PI * tf * (1.0 - f)).log2())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dfr_models() {
+        let coll = CollectionStats { n_docs: 1000, doc_lens: vec![], total_len: 100_000, avgdl: 100.0, doc_norms: Default::default() };
+        let rare = TermStats { df: 10.0, cf: 20.0, qtf: 1.0 };
+        let common = TermStats { df: 500.0, cf: 2000.0, qtf: 1.0 };
+        let doc = DocStats { id: 0, len: 100.0 };
+
+        // hand-computed: tfn = 2 log2(2) = 2, lambda = 0.02
+        let expect = (2.0 * 100f32.log2() + (0.02 + 1.0 / 24.0 - 2.0) * E.log2() + 0.5 * (4.0 * PI).log2()) / 3.0;
+        assert!((PL2::default().score(2.0, &rare, &doc, &coll) - expect).abs() < 1e-4);
+        assert!((InL2::default().score(2.0, &rare, &doc, &coll) - 2.0 / 3.0 * (1001f32 / 10.5).log2()).abs() < 1e-4);
+
+        for sim in [&PL2::default() as &dyn Similarity, &InL2::default(), &DPH] {
+            let s = |tf, t: &TermStats, len| sim.score(tf, t, &DocStats { id: 0, len }, &coll);
+            assert!(s(3.0, &rare, 100.0) > s(1.0, &rare, 100.0), "more occurrences score higher");
+            assert!(s(2.0, &rare, 100.0) > s(2.0, &common, 100.0), "rarer terms score higher");
+            assert!(s(2.0, &rare, 100.0) > s(2.0, &rare, 400.0), "longer docs score lower");
+        }
+        assert_eq!(DPH.score(5.0, &rare, &DocStats { id: 0, len: 5.0 }, &coll), 0.0);
+    }
+}
@@ -0,0 +1,108 @@
+use crate::rank::similarity::{CollectionStats, DocStats, Similarity, TermStats};
+
+/// Query likelihood with Dirichlet-prior smoothing (Zhai & Lafferty 2001),
+/// in its rank-equivalent form: per matching term
+/// `qtf ln(1 + tf / (mu p(t|C)))`, plus `sum(qtf) ln(mu / (dl + mu))` per
+/// document.
+/// Scores are log-probabilities up to a query constant, so may be negative.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Dirichlet {
+    pub mu: f32,
+}
+
+impl Default for Dirichlet {
+    fn default() -> Self { Self { mu: 2000.0 } }
+}
+
+/// Collection language model `p(t|C) = cf / |C|`, or `None` for unseen terms.
+fn p_coll(term: &TermStats, coll: &CollectionStats) -> Option<f32> {
+    let total = coll.total_len as f32;
+    (term.cf > 0.0 && total > 0.0).then(|| term.cf / total)
+}
+
+impl Similarity for Dirichlet {
+    fn score(&self, tf: f32, term: &TermStats, _doc: &DocStats, coll: &CollectionStats) -> f32 {
+        p_coll(term, coll).map_or(0.0, |p| term.qtf * (1.0 + tf / (self.mu * p)).ln())
+    }
+
+    fn doc_score(&self, doc: &DocStats, _coll: &CollectionStats, terms: &[TermStats]) -> f32 {
+        terms.iter().map(|t| t.qtf).sum::<f32>() * (self.mu / (doc.len + self.mu)).ln()
+    }
+}
+
+/// Query likelihood with Jelinek-Mercer smoothing: the document model is
+/// mixed with the collection model with weight `lambda`; per matching term
+/// `qtf ln(1 + (1 - lambda) tf / dl / (lambda p(t|C)))`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JelinekMercer {
+    pub lambda: f32,
+}
+
+impl Default for JelinekMercer {
+    fn default() -> Self { Self { lambda: 0.1 } }
+}
+
+impl Similarity for JelinekMercer {
+    fn score(&self, tf: f32, term: &TermStats, doc: &DocStats, coll: &CollectionStats) -> f32 {
+        match p_coll(term, coll) {
+            Some(p) if doc.len > 0.0 => term.qtf * (1.0 + (1.0 - self.lambda) * tf / doc.len / (self.lambda * p)).ln(),
+            _ => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rank::similarity::{top_k, TermPostings};
+
+    /// Smoothed log-likelihood of the query under each document's model.
+    fn log_likelihood(p_doc: impl Fn(f32, f32, f32) -> f32, query: &[(&[f32], f32)], lens: &[u32], total: f32) -> Vec<f32> {
+        (0..lens.len()).map(|d| query.iter().map(|(tfs, cf)| p_doc(tfs[d], lens[d] as f32, cf / total).ln()).sum()).collect()
+    }
+
+    #[test]
+    fn ranks_like_full_query_likelihood() {
+        let lens = vec![10, 40, 25, 5];
+        let coll = CollectionStats::from_doc_lens(lens.clone(), None);
+        let total = coll.total_len as f32;
+        // per term: tf in each doc and the collection frequency
+        let t1: (&[f32], f32) = (&[2.0, 3.0, 0.0, 1.0], 6.0);
+        let t2: (&[f32], f32) = (&[0.0, 4.0, 1.0, 0.0], 5.0);
+        let terms: Vec<TermPostings> = [t1, t2]
+            .iter()
+            .map(|(tfs, cf)| {
+                let ps = tfs.iter().enumerate().filter(|(_, &tf)| tf > 0.0).map(|(d, &tf)| (d as u32, tf)).collect();
+                TermPostings::new(ps).with_cf(*cf)
+            })
+            .collect();
+
+        let order = |scores: Vec<f32>| {
+            let mut ids: Vec<u32> = (0..scores.len() as u32).collect();
+            ids.sort_by(|&a, &b| scores[b as usize].total_cmp(&scores[a as usize]));
+            ids
+        };
+        let ranked = |sim: &dyn Similarity| top_k(sim, &coll, &terms, Some(&[0, 1, 2, 3]), 10).iter().map(|h| h.doc).collect::<Vec<_>>();
+
+        let mu = 20.0;
+        let full = log_likelihood(|tf, dl, p| (tf + mu * p) / (dl + mu), &[t1, t2], &lens, total);
+        assert_eq!(ranked(&Dirichlet { mu }), order(full));
+
+        let lambda = 0.3;
+        let full = log_likelihood(|tf, dl, p| (1.0 - lambda) * tf / dl + lambda * p, &[t1, t2], &lens, total);
+        assert_eq!(ranked(&JelinekMercer { lambda }), order(full));
+
+        // a query term repeated 3 times counts 3 times, smoothing mass included
+        let terms: Vec<TermPostings> = vec![terms[0].clone(), terms[1].clone().with_qtf(3.0)];
+        let scores = |sim: &dyn Similarity| {
+            let mut hits = top_k(sim, &coll, &terms, Some(&[0, 1, 2, 3]), 10);
+            hits.sort_by_key(|h| h.doc);
+            hits.iter().map(|h| h.score).collect::<Vec<_>>()
+        };
+        let full = log_likelihood(|tf, dl, p| (tf + mu * p) / (dl + mu), &[t1, t2, t2, t2], &lens, total);
+        let got = scores(&Dirichlet { mu });
+        // rank-equivalent: differs from the full likelihood by a per-query constant
+        let shift = full[0] - got[0];
+        assert!(full.iter().zip(&got).all(|(f, g)| (f - g - shift).abs() < 1e-4), "{full:?} vs {got:?}");
+    }
+}
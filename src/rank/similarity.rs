@@ -0,0 +1,266 @@
+use crate::index::inverted::InvertedIndex;
+use crate::rank::bm25::{BM25, BM25L, BM25Plus};
+use crate::rank::dfr::{InL2, DPH, PL2};
+use crate::rank::lm::{Dirichlet, JelinekMercer};
+use crate::rank::vsm::{Smart, Vsm};
+use anyhow::*;
+use std::collections::{BTreeMap, HashMap};
+
+/// Collection-level statistics used for scoring.
+#[derive(Debug, Clone)]
+pub struct CollectionStats {
+    pub n_docs: u32,
+    /// Token count per doc id (index = doc id).
+    pub doc_lens: Vec<u32>,
+    /// Tokens in the whole collection (the sum of `doc_lens`).
+    pub total_len: u64,
+    pub avgdl: f32,
+    /// Per SMART document weighting, each doc's vector norm (see
+    /// `rank::vsm::doc_norms`); only filled by `with_doc_norms`.
+    pub doc_norms: BTreeMap<String, Vec<f32>>,
+}
+
+impl CollectionStats {
+    /// Derive stats from the postings. Doc ids are assumed dense from 0;
+    /// `n_docs` defaults to the index's own.
+    pub fn from_index(inv: &InvertedIndex, n_docs: Option<u32>) -> Self {
+        let mut doc_lens: Vec<u32> = Vec::new();
+        for term in inv.dict.keys() {
+            for (doc, positions) in inv.postings(term) {
+                let d = doc as usize;
+                if d >= doc_lens.len() { doc_lens.resize(d + 1, 0); }
+                doc_lens[d] += positions.len() as u32;
+            }
+        }
+        Self::from_doc_lens(doc_lens, Some(n_docs.unwrap_or(inv.n_docs)))
+    }
+
+    /// Add the doc norms `sim` scores with (if any), for this `n_docs`.
+    pub fn with_doc_norms<S: Similarity + ?Sized>(mut self, inv: &InvertedIndex, sim: &S) -> Result<Self> {
+        if let Some(w) = sim.doc_weighting() {
+            if !self.doc_norms.contains_key(&w.norm_key()) {
+                let norms = inv.doc_norms(&w, self.n_docs)?;
+                self.doc_norms.insert(w.norm_key(), norms);
+            }
+        }
+        Ok(self)
+    }
+
+    /// Stats from per-doc token counts, padded to `n_docs` when given.
+    pub fn from_doc_lens(mut doc_lens: Vec<u32>, n_docs: Option<u32>) -> Self {
+        let n_docs = n_docs.unwrap_or(doc_lens.len() as u32).max(doc_lens.len() as u32);
+        doc_lens.resize(n_docs as usize, 0);
+        let total_len: u64 = doc_lens.iter().map(|&l| l as u64).sum();
+        let avgdl = if n_docs > 0 && total_len > 0 { total_len as f32 / n_docs as f32 } else { 1.0 };
+        Self { n_docs, doc_lens, total_len, avgdl, doc_norms: BTreeMap::new() }
+    }
+
+    pub fn doc_len(&self, doc: u32) -> u32 {
+        self.doc_lens.get(doc as usize).copied().unwrap_or(0)
+    }
+}
+
+/// Collection statistics of one query term (or phrase clause).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TermStats {
+    /// Documents containing the term.
+    pub df: f32,
+    /// Occurrences of the term in the whole collection.
+    pub cf: f32,
+    /// Occurrences of the term in the query.
+    pub qtf: f32,
+}
+
+impl TermStats {
+    /// Stats of a term from its `(doc, tf)` postings.
+    pub fn from_postings(postings: &[(u32, f32)]) -> Self {
+        Self { df: postings.len() as f32, cf: postings.iter().map(|&(_, tf)| tf).sum(), qtf: 1.0 }
+    }
+}
+
+/// One query term's `(doc, tf)` postings with its collection stats.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TermPostings {
+    pub stats: TermStats,
+    pub postings: Vec<(u32, f32)>,
+}
+
+impl TermPostings {
+    /// Stats derived from the postings themselves.
+    pub fn new(postings: Vec<(u32, f32)>) -> Self {
+        Self { stats: TermStats::from_postings(&postings), postings }
+    }
+
+    /// Use a stored collection frequency (e.g. `InvertedIndex::cf`).
+    pub fn with_cf(mut self, cf: f32) -> Self {
+        self.stats.cf = cf;
+        self
+    }
+
+    /// Count the term `qtf` times in the query.
+    pub fn with_qtf(mut self, qtf: f32) -> Self {
+        self.stats.qtf = qtf;
+        self
+    }
+}
+
+/// Statistics of the document being scored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DocStats {
+    pub id: u32,
+    pub len: f32,
+}
+
+/// A ranking function: the score one term with frequency `tf` contributes
+/// to a document. Scores of a document's terms are summed, plus
+/// `doc_score` once per ranked document, and divided by `query_norm`.
+pub trait Similarity: Send + Sync {
+    fn score(&self, tf: f32, term: &TermStats, doc: &DocStats, coll: &CollectionStats) -> f32;
+
+    /// Part of the score that depends on the document and the query terms
+    /// only, whether they match or not (e.g. smoothing mass in language models).
+    fn doc_score(&self, _doc: &DocStats, _coll: &CollectionStats, _terms: &[TermStats]) -> f32 { 0.0 }
+
+    /// Normalizer for the whole query (e.g. the query vector's length).
+    fn query_norm(&self, _terms: &[TermStats], _coll: &CollectionStats) -> f32 { 1.0 }
+
+    /// SMART document weighting whose norms must be in
+    /// `CollectionStats::doc_norms`, if any.
+    fn doc_weighting(&self) -> Option<Smart> { None }
+}
+
+impl<S: Similarity + ?Sized> Similarity for Box<S> {
+    fn score(&self, tf: f32, term: &TermStats, doc: &DocStats, coll: &CollectionStats) -> f32 {
+        (**self).score(tf, term, doc, coll)
+    }
+
+    fn doc_score(&self, doc: &DocStats, coll: &CollectionStats, terms: &[TermStats]) -> f32 {
+        (**self).doc_score(doc, coll, terms)
+    }
+
+    fn query_norm(&self, terms: &[TermStats], coll: &CollectionStats) -> f32 {
+        (**self).query_norm(terms, coll)
+    }
+
+    fn doc_weighting(&self) -> Option<Smart> {
+        (**self).doc_weighting()
+    }
+}
+
+/// Names accepted by [`similarity_by_name`].
+pub const SIMILARITIES: &[&str] = &["bm25", "bm25+", "bm25l", "lm-dirichlet", "lm-jm", "vsm", "pl2", "inl2", "dph"];
+
+/// Optional parameters for [`similarity_by_name`]; unset ones take each
+/// model's default.
+#[derive(Debug, Clone, Default)]
+pub struct SimilarityParams {
+    pub k1: Option<f32>,
+    pub b: Option<f32>,
+    pub delta: Option<f32>,
+    /// Dirichlet prior
+    pub mu: Option<f32>,
+    /// Jelinek-Mercer collection weight
+    pub lambda: Option<f32>,
+    /// SMART weighting scheme for `vsm`, e.g. `lnc.ltc`
+    pub smart: Option<String>,
+    /// DFR term frequency normalization (PL2, InL2)
+    pub c: Option<f32>,
+}
+
+/// A similarity chosen by name (see [`SIMILARITIES`]). `bm25f` is refused:
+/// it needs per-field statistics, see [`crate::rank::bm25::rank_fields`].
+pub fn similarity_by_name(name: &str, p: &SimilarityParams) -> Result<Box<dyn Similarity>> {
+    let d = BM25::default();
+    let bm25 = BM25::new(p.k1.unwrap_or(d.k1), p.b.unwrap_or(d.b));
+    Ok(match name.to_ascii_lowercase().as_str() {
+        "bm25" => Box::new(bm25),
+        "bm25+" | "bm25plus" => Box::new(BM25Plus { bm25, delta: p.delta.unwrap_or(BM25Plus::default().delta) }),
+        "bm25l" => Box::new(BM25L { bm25, delta: p.delta.unwrap_or(BM25L::default().delta) }),
+        "bm25f" => bail!("bm25f ranks documents with fields, and this index has none (query-inv-rank takes tab-separated fields with --field-weights)"),
+        "lm-dirichlet" | "dirichlet" => Box::new(Dirichlet { mu: p.mu.unwrap_or(Dirichlet::default().mu) }),
+        "lm-jm" | "jelinek-mercer" => {
+            let lambda = p.lambda.unwrap_or(JelinekMercer::default().lambda);
+            ensure!(lambda > 0.0 && lambda < 1.0, "lambda must be in (0, 1), got {lambda}");
+            Box::new(JelinekMercer { lambda })
+        }
+        "vsm" | "tfidf" => Box::new(p.smart.as_deref().map_or_else(|| Ok(Vsm::default()), Vsm::parse)?),
+        "pl2" => Box::new(PL2 { c: p.c.unwrap_or(PL2::default().c) }),
+        "inl2" => Box::new(InL2 { c: p.c.unwrap_or(InL2::default().c) }),
+        "dph" => Box::new(DPH),
+        _ => bail!("unknown similarity {name:?} (expected one of {})", SIMILARITIES.join(", ")),
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hit {
+    pub doc: u32,
+    pub score: f32,
+}
+
+/// The `k` best documents for a query given as one postings list per term,
+/// by score desc then doc id. With `docs` (sorted), exactly those documents
+/// are ranked; otherwise every document in some postings list is.
+pub fn top_k<S: Similarity + ?Sized>(
+    sim: &S,
+    coll: &CollectionStats,
+    terms: &[TermPostings],
+    docs: Option<&[u32]>,
+    k: usize,
+) -> Vec<Hit> {
+    let doc_stats = |doc: u32| DocStats { id: doc, len: coll.doc_len(doc) as f32 };
+    let mut scores: HashMap<u32, f32> = match docs {
+        Some(docs) => docs.iter().map(|&d| (d, 0.0)).collect(),
+        None => terms.iter().flat_map(|t| t.postings.iter().map(|&(d, _)| (d, 0.0))).collect(),
+    };
+    for t in terms {
+        for &(doc, tf) in &t.postings {
+            if let Some(s) = scores.get_mut(&doc) {
+                *s += sim.score(tf, &t.stats, &doc_stats(doc), coll);
+            }
+        }
+    }
+    let stats: Vec<TermStats> = terms.iter().map(|t| t.stats).collect();
+    let qnorm = sim.query_norm(&stats, coll);
+    for (&doc, s) in scores.iter_mut() {
+        *s = (*s + sim.doc_score(&doc_stats(doc), coll, &stats)) / qnorm;
+    }
+
+    take_top(scores.into_iter().map(|(doc, score)| Hit { doc, score }).collect(), k)
+}
+
+/// The `k` best of `hits`, by score desc then doc id.
+pub(crate) fn take_top(mut hits: Vec<Hit>, k: usize) -> Vec<Hit> {
+    let order = |a: &Hit, b: &Hit| b.score.total_cmp(&a.score).then(a.doc.cmp(&b.doc));
+    if hits.len() > k && k > 0 {
+        hits.select_nth_unstable_by(k - 1, order);
+    }
+    hits.truncate(k);
+    hits.sort_by(order);
+    hits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rank::bm25::BM25;
+
+    #[test]
+    fn top_k_orders_and_filters() {
+        let coll = CollectionStats::from_doc_lens(vec![4, 4, 8, 4], None);
+        let terms = vec![TermPostings::new(vec![(0, 1.0), (2, 2.0)]), TermPostings::new(vec![(1, 1.0), (2, 1.0)])];
+        let all = top_k(&BM25::default(), &coll, &terms, None, 10);
+        assert_eq!(all.iter().map(|h| h.doc).collect::<Vec<_>>(), vec![2, 0, 1]);
+        assert_eq!(top_k(&BM25::default(), &coll, &terms, None, 1), all[..1]);
+
+        let some = top_k(&BM25::default(), &coll, &terms, Some(&[1, 3]), 10);
+        assert_eq!(some, vec![all[2].clone(), Hit { doc: 3, score: 0.0 }]);
+    }
+
+    #[test]
+    fn total_len_is_exact() {
+        // an avgdl of 16_777_217 does not fit in an f32; an empty collection has no tokens
+        let coll = CollectionStats::from_doc_lens(vec![16_777_217, 16_777_216, 16_777_218], None);
+        assert_eq!(coll.total_len, 3 * 16_777_217);
+        assert_eq!(CollectionStats::from_doc_lens(vec![], Some(4)).total_len, 0);
+    }
+}
@@ -0,0 +1,170 @@
+use crate::rank::similarity::{CollectionStats, DocStats, Similarity, TermStats};
+use anyhow::*;
+
+/// SMART term frequency letters: natural, logarithmic, boolean.
+const TF: &[u8] = b"nlb";
+/// SMART document frequency letters: none, idf, probabilistic idf.
+const DF: &[u8] = b"ntp";
+
+fn tf_weight(code: u8, tf: f32) -> f32 {
+    match code {
+        b'l' if tf > 0.0 => 1.0 + tf.ln(),
+        b'b' if tf > 0.0 => 1.0,
+        b'n' => tf,
+        _ => 0.0,
+    }
+}
+
+fn df_weight(code: u8, df: f32, n_docs: f32) -> f32 {
+    if df <= 0.0 { return 0.0; }
+    match code {
+        b't' => (n_docs / df).ln().max(0.0),
+        b'p' => ((n_docs - df) / df).ln().max(0.0),
+        _ => 1.0,
+    }
+}
+
+/// One side of a SMART scheme, e.g. `lnc`: tf, df and normalization letters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Smart {
+    pub tf: u8,
+    pub df: u8,
+    /// `c` for cosine normalization, `n` for none.
+    pub norm: u8,
+}
+
+impl Smart {
+    fn weight(&self, tf: f32, df: f32, n_docs: f32) -> f32 { tf_weight(self.tf, tf) * df_weight(self.df, df, n_docs) }
+
+    /// Key of this weighting's norms in `CollectionStats::doc_norms`, e.g. `"lt"`.
+    pub fn norm_key(&self) -> String { format!("{}{}", self.tf as char, self.df as char) }
+}
+
+/// Euclidean norm of every document's vector under weighting `w`, from
+/// `(doc, tf)` postings per term, with one entry per doc id below `n_docs`.
+pub fn doc_norms<'a>(w: &Smart, n_docs: u32, terms: impl IntoIterator<Item = &'a [(u32, u32)]>) -> Vec<f32> {
+    let mut sums = vec![0f64; n_docs as usize];
+    for postings in terms {
+        let df = postings.len() as f32;
+        for &(doc, tf) in postings {
+            let x = w.weight(tf as f32, df, n_docs as f32) as f64;
+            sums[doc as usize] += x * x;
+        }
+    }
+    sums.into_iter().map(|x| x.sqrt() as f32).collect()
+}
+
+/// Vector space model with SMART `ddd.qqq` weighting (e.g. `lnc.ltc`): the
+/// score is the dot product of the document and query weight vectors, each
+/// optionally cosine-normalized. A term repeated in the query has that
+/// query tf. With `c` on both sides and plain terms, scores are cosines in
+/// [0, 1]. Document norms come from `CollectionStats::doc_norms` (see
+/// `CollectionStats::with_doc_norms`); a document without one counts as
+/// norm 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Vsm {
+    pub doc: Smart,
+    pub query: Smart,
+}
+
+impl Default for Vsm {
+    fn default() -> Self { Self::parse("lnc.ltc").unwrap() }
+}
+
+impl Vsm {
+    pub fn parse(scheme: &str) -> Result<Self> {
+        let side = |s: &str| -> Result<Smart> {
+            let &[tf, df, norm] = s.as_bytes() else { bail!("SMART scheme {scheme:?}: each side needs three letters") };
+            ensure!(TF.contains(&tf), "SMART scheme {scheme:?}: tf must be one of n, l, b");
+            ensure!(DF.contains(&df), "SMART scheme {scheme:?}: df must be one of n, t, p");
+            ensure!(norm == b'n' || norm == b'c', "SMART scheme {scheme:?}: normalization must be n or c");
+            Ok(Smart { tf, df, norm })
+        };
+        let Some((d, q)) = scheme.split_once('.') else { bail!("SMART scheme {scheme:?} must look like lnc.ltc") };
+        Ok(Self { doc: side(d)?, query: side(q)? })
+    }
+
+    fn doc_norm(&self, doc: &DocStats, coll: &CollectionStats) -> f32 {
+        if self.doc.norm != b'c' { return 1.0; }
+        coll.doc_norms.get(&self.doc.norm_key()).and_then(|n| n.get(doc.id as usize)).copied().unwrap_or(1.0)
+    }
+}
+
+impl Similarity for Vsm {
+    fn doc_weighting(&self) -> Option<Smart> { (self.doc.norm == b'c').then_some(self.doc) }
+
+    fn score(&self, tf: f32, term: &TermStats, doc: &DocStats, coll: &CollectionStats) -> f32 {
+        let n = coll.n_docs as f32;
+        let norm = self.doc_norm(doc, coll);
+        if norm == 0.0 { return 0.0; }
+        self.doc.weight(tf, term.df, n) / norm * self.query.weight(term.qtf, term.df, n)
+    }
+
+    fn query_norm(&self, terms: &[TermStats], coll: &CollectionStats) -> f32 {
+        if self.query.norm != b'c' { return 1.0; }
+        let n = coll.n_docs as f32;
+        let sq: f32 = terms.iter().map(|t| self.query.weight(t.qtf, t.df, n).powi(2)).sum();
+        if sq > 0.0 { sq.sqrt() } else { 1.0 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::inverted::InvBuilder;
+    use crate::rank::similarity::{top_k, TermPostings};
+
+    #[test]
+    fn cosine_scores() {
+        assert!(Vsm::parse("lnc.xtc").is_err() && Vsm::parse("lnc").is_err());
+        let mut b = InvBuilder::default();
+        b.add_doc(0, "apple banana");
+        b.add_doc(1, "apple apple cherry durian");
+        b.add_doc(2, "cherry");
+        let inv = b.finalize();
+        let nnc = Vsm::parse("nnc.nnc").unwrap();
+        let coll = CollectionStats::from_index(&inv, None).with_doc_norms(&inv, &nnc).unwrap();
+        assert_eq!(coll.doc_norms.keys().collect::<Vec<_>>(), ["nn"]);
+        assert_eq!(coll.doc_norms["nn"], vec![2f32.sqrt(), 6f32.sqrt(), 1.0]);
+
+        let query = |q: &[&str]| -> Vec<TermPostings> {
+            q.iter().map(|t| TermPostings::new(inv.postings(t).into_iter().map(|(d, p)| (d, p.len() as f32)).collect())).collect()
+        };
+        // a document identical to the query has cosine 1 under nnc.nnc
+        let hits = top_k(&nnc, &coll, &query(&["apple", "banana"]), None, 10);
+        assert_eq!(hits[0].doc, 0);
+        assert!((hits[0].score - 1.0).abs() < 1e-6);
+        // a repeated query term is one clause with query tf 2: cos = 3 / sqrt(10)
+        let mut repeated = query(&["apple", "banana"]);
+        repeated[0] = repeated[0].clone().with_qtf(2.0);
+        let hits = top_k(&nnc, &coll, &repeated, None, 10);
+        assert_eq!(hits[0].doc, 0);
+        assert!((hits[0].score - 3.0 / 10f32.sqrt()).abs() < 1e-6);
+        // lnc.ltc: scores stay in [0, 1], rare terms weigh more
+        let coll = coll.with_doc_norms(&inv, &Vsm::default()).unwrap();
+        let hits = top_k(&Vsm::default(), &coll, &query(&["apple", "cherry", "durian"]), None, 10);
+        assert_eq!(hits.iter().map(|h| h.doc).collect::<Vec<_>>(), vec![1, 2, 0]);
+        assert!(hits.iter().all(|h| (0.0..=1.0).contains(&h.score)));
+    }
+
+    #[test]
+    fn norms_use_the_scoring_collection_size() {
+        let mut b = InvBuilder::default();
+        b.add_doc(0, "apple banana");
+        b.add_doc(1, "apple cherry");
+        b.add_doc(2, "");
+        let inv = b.finalize();
+        let ltc = Vsm::parse("ltc.ltc").unwrap();
+        let query: Vec<TermPostings> = ["apple", "banana"]
+            .iter()
+            .map(|t| TermPostings::new(inv.postings(t).into_iter().map(|(d, p)| (d, p.len() as f32)).collect()))
+            .collect();
+        for n_docs in [None, Some(3), Some(5)] {
+            let coll = CollectionStats::from_index(&inv, n_docs).with_doc_norms(&inv, &ltc).unwrap();
+            assert_eq!(coll.n_docs, n_docs.unwrap_or(3));
+            assert_eq!(coll.doc_norms["lt"].len(), coll.n_docs as usize);
+            let hits = top_k(&ltc, &coll, &query, None, 10);
+            assert!((hits[0].score - 1.0).abs() < 1e-6, "{n_docs:?}: {hits:?}");
+        }
+    }
+}
@@ -1,2 +1,4 @@
-pub fn tokenize(s:&str)->Vec<(String,usize)>{ let mut out=Vec::new(); let mut cur=String::new(); for (i,ch) in s.char_indices(){ if ch.is_alphanumeric(){cur.push(ch.to_ascii_lowercase());} else { if !cur.is_empty(){ out.push((std::mem::take(&mut cur),i)); }}} if !cur.is_empty(){ out.push((cur,s.len())); } out }
-#[cfg(test)] mod tests{ use super::*; #[test] fn toks(){ let v=tokenize("Hello, world! Hello?"); let terms:Vec<_>=v.iter().map(|(t,_)| t.as_str()).collect(); assert_eq!(terms,vec!["hello","world","hello"]); } }
+pub fn tokenize(s:&str)->Vec<(String,usize)>{ tokenize_spans(s).into_iter().map(|(t,_,end)| (t,end)).collect() }
+/// Tokens with their byte span in `s`: (term, start, end).
+pub fn tokenize_spans(s:&str)->Vec<(String,usize,usize)>{ let mut out=Vec::new(); let mut cur=String::new(); let mut start=0; for (i,ch) in s.char_indices(){ if ch.is_alphanumeric(){ if cur.is_empty(){start=i;} cur.push(ch.to_ascii_lowercase());} else { if !cur.is_empty(){ out.push((std::mem::take(&mut cur),start,i)); }}} if !cur.is_empty(){ out.push((cur,start,s.len())); } out }
+#[cfg(test)] mod tests{ use super::*; #[test] fn toks(){ let v=tokenize("Hello, world! Hello?"); let terms:Vec<_>=v.iter().map(|(t,_)| t.as_str()).collect(); assert_eq!(terms,vec!["hello","world","hello"]); } #[test] fn spans(){ assert_eq!(tokenize_spans("Hi, yo"),vec![("hi".into(),0,2),("yo".into(),4,6)]); } }
@@ -1,7 +1,7 @@
 pub struct RankBitVec{bits:Vec<u64>,super_:Vec<u64>,block:Vec<u16>}
 impl RankBitVec{
     pub fn from_bits(bits:Vec<u64>)->Self{
-        let n64=bits.len(); let mut super_=Vec::with_capacity((n64+7)/8+1); let mut block=Vec::with_capacity(n64+1);
+        let n64=bits.len(); let mut super_=Vec::with_capacity(n64.div_ceil(8)+1); let mut block=Vec::with_capacity(n64+1);
         let mut acc_super: u64=0; let mut acc_block:u16=0;
         for (i,&w) in bits.iter().enumerate(){
             if i%8==0{ super_.push(acc_super); acc_block=0; }
@@ -9,15 +9,19 @@ impl RankBitVec{
             let pc=w.count_ones() as u16; acc_block=acc_block.wrapping_add(pc);
             if i%8==7{ acc_super+=acc_block as u64; }
         }
-        block.push(acc_block); if n64%8!=0{ super_.push(acc_super); }
+        block.push(acc_block); if !n64.is_multiple_of(8){ super_.push(acc_super); }
         Self{bits,super_,block}
     }
     #[inline] fn word_at(&self,i:usize)->u64{ self.bits[i] }
+    #[inline] pub fn get(&self,i:usize)->bool{ (self.bits[i>>6]>>(i&63))&1==1 }
     pub fn rank1(&self,pos:usize)->u64{
-        let word=pos>>6; let bit=pos&63; let sb=word>>3;
-        let base=self.super_[sb]+self.block[word] as u64;
-        let mask=if bit==63{u64::MAX}else{(1u64<<bit)-1};
+        let word=pos>>6; let bit=pos&63;
+        // pos == len on a word boundary: everything, i.e. the last super block plus its running count
+        if word==self.bits.len(){ return if word==0{0}else{self.super_[(word-1)>>3]+self.block[word] as u64}; }
+        let base=self.super_[word>>3]+self.block[word] as u64;
+        let mask=(1u64<<bit)-1;
         base+(self.word_at(word)&mask).count_ones() as u64
     }
 }
-#[cfg(test)] mod tests{ use super::*; #[test] fn small(){ let r=RankBitVec::from_bits(vec![0b01101001u8 as u64]); assert_eq!(r.rank1(0),0); assert_eq!(r.rank1(1),1); assert_eq!(r.rank1(4),2); } }
+#[cfg(test)] mod tests{ use super::*; #[test] fn small(){ let r=RankBitVec::from_bits(vec![0b01101001u8 as u64]); assert_eq!(r.rank1(0),0); assert_eq!(r.rank1(1),1); assert_eq!(r.rank1(4),2); }
+    #[test] fn word_edges(){ let r=RankBitVec::from_bits(vec![u64::MAX;8]); assert_eq!(r.rank1(63),63); assert_eq!(r.rank1(64),64); assert_eq!(r.rank1(512),512); } }
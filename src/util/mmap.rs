@@ -1,13 +1,17 @@
 use memmap2::{Mmap, MmapMut, MmapOptions};
 use std::fs::File;
-use std::io::{Result, Write};
+use std::io::Result;
 
 /// Open a file and return an owned read-only memory map.
 /// Use it like: `let mmap = mmap_read(path)?; let bytes: &[u8] = &mmap[..];`
 pub fn mmap_read(path: &str) -> Result<Mmap> {
-    let file = File::open(path)?;
-    // SAFETY: mapping read-only; file lives as long as mmap handle.
-    unsafe { MmapOptions::new().map(&file) }
+    mmap_file(&File::open(path)?)
+}
+
+/// Read-only memory map of an already open file (e.g. a temp file).
+pub fn mmap_file(file: &File) -> Result<Mmap> {
+    // SAFETY: mapping read-only; the map keeps its own handle to the file.
+    unsafe { MmapOptions::new().map(file) }
 }
 
 /// Create/resize a file and return an owned writable memory map plus the file handle.
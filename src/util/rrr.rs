@@ -1,131 +1,139 @@
 use anyhow::*;
 
 /// RRR-style compressed flags: we store superblock prefix sums (every 512 bits)
-/// and the positions of the 1-bits as u32s. rank1(pos) = super_counts[p/512] + count(ones <= pos in that window).
+/// and the positions of the 1-bits. rank1(pos) = super_counts[p/512] + count(ones < pos in that window).
 /// This is extremely small for sparse bitmaps (like "every k-th SA rank") and is fast enough.
+///
+/// Positions and counts are u64 in memory. On disk they take 4 bytes each
+/// when `nbits` fits in u32 and 8 bytes otherwise (see `save`).
 #[derive(Clone)]
 pub struct CompressedFlags {
     nbits: usize,
     super_every: usize,        // 512
-    super_counts: Vec<u32>,    // prefix counts every 512 bits
-    ones: Vec<u32>,            // absolute positions of 1-bits (sorted)
-    // index to speed up local search: start offsets into `ones` per superblock
-    super_offsets: Vec<u32>,   // index into `ones` for each superblock
+    super_counts: Vec<u64>,    // prefix counts every 512 bits; also each superblock's first index into `ones`
+    ones: Vec<u64>,            // absolute positions of 1-bits (sorted)
 }
 
 impl CompressedFlags {
     pub fn build(nbits: usize, one_positions: &[usize]) -> Self {
         let super_every = 512;
-        let n_super = (nbits + super_every - 1) / super_every + 1;
+        let n_super = nbits.div_ceil(super_every) + 1;
 
-        let mut super_counts = vec![0u32; n_super];
-        let mut ones_u32 = Vec::with_capacity(one_positions.len());
-        for &p in one_positions {
-            ones_u32.push(p as u32);
-        }
-        ones_u32.sort_unstable();
+        let mut super_counts = vec![0u64; n_super];
+        let mut ones: Vec<u64> = one_positions.iter().map(|&p| p as u64).collect();
+        ones.sort_unstable();
 
         // super_counts[i] = number of ones before bit i*512
         let mut idx = 0usize;
-        for i in 0..n_super {
-            let bound = i.saturating_mul(super_every) as u32;
-            while idx < ones_u32.len() && ones_u32[idx] < bound {
+        for (i, count) in super_counts.iter_mut().enumerate() {
+            let bound = (i as u64).saturating_mul(super_every as u64);
+            while idx < ones.len() && ones[idx] < bound {
                 idx += 1;
             }
-            super_counts[i] = idx as u32;
-        }
-
-        // For fast intra-superblock rank, store the starting index in `ones` for each super.
-        let mut super_offsets = vec![0u32; n_super];
-        for i in 0..n_super {
-            super_offsets[i] = super_counts[i];
+            *count = idx as u64;
         }
 
-        Self {
-            nbits,
-            super_every,
-            super_counts,
-            ones: ones_u32,
-            super_offsets,
-        }
+        Self { nbits, super_every, super_counts, ones }
     }
 
     /// rank1(pos): number of 1s in [0, pos), pos in bits.
     pub fn rank1(&self, pos: usize) -> u64 {
         if pos == 0 { return 0; }
-        let pos_u = (pos - 1) as u32;
         let sb = pos / self.super_every;
-        let base = self.super_counts[sb] as u64;
-
-        // Search ones in [sb*512, pos)
-        let start_idx = self.super_offsets[sb] as usize;
-        let start_bit = (sb * self.super_every) as u32;
-
-        // Binary search upper_bound on ones[..] for pos_u
-        let slice = &self.ones[start_idx..];
-        let mut lo = 0usize;
-        let mut hi = slice.len();
-        while lo < hi {
-            let mid = (lo + hi) / 2;
-            if slice[mid] < pos_u { lo = mid + 1; } else { hi = mid; }
-        }
+        let base = self.super_counts[sb];
 
-        // slice[..lo] are <= pos-1; ensure inside the current superblock
-        let mut count = 0usize;
-        for k in 0..lo {
-            if slice[k] >= start_bit && slice[k] < pos_u { count += 1; }
-        }
+        // ones[base..] all lie at or after sb*512; count those < pos
+        let slice = &self.ones[base as usize..];
+        let end = slice.len().min(self.super_every);
+        let count = slice[..end].partition_point(|&p| p < pos as u64);
         base + count as u64
     }
 
     /// Serialize to bytes (little-endian):
-    /// u64 nbits, u64 n_super, then n_super * u32 super_counts[]
-    /// u64 n_ones, then n_ones * u32 ones[]
+    /// u64 nbits, u8 width (4 or 8), u64 n_super, then n_super * width super_counts[]
+    /// u64 n_ones, then n_ones * width ones[]
     pub fn save(&self, w: &mut impl std::io::Write) -> Result<()> {
+        let width: u8 = if self.nbits as u64 <= u32::MAX as u64 { 4 } else { 8 };
         w.write_all(&(self.nbits as u64).to_le_bytes())?;
-        w.write_all(&(self.super_counts.len() as u64).to_le_bytes())?;
-        for &v in &self.super_counts {
-            w.write_all(&v.to_le_bytes())?;
-        }
-        w.write_all(&(self.ones.len() as u64).to_le_bytes())?;
-        for &v in &self.ones {
-            w.write_all(&v.to_le_bytes())?;
+        w.write_all(&[width])?;
+        for vs in [&self.super_counts, &self.ones] {
+            w.write_all(&(vs.len() as u64).to_le_bytes())?;
+            for &v in vs.iter() {
+                w.write_all(&v.to_le_bytes()[..width as usize])?;
+            }
         }
         Ok(())
     }
 
     pub fn load(r: &mut impl std::io::Read) -> Result<Self> {
         let mut b8 = [0u8; 8];
-        let mut b4 = [0u8; 4];
-
         r.read_exact(&mut b8)?;
-        let nbits = u64::from_le_bytes(b8) as usize;
+        let nbits = u64::from_le_bytes(b8);
+        let mut width = [0u8; 1];
+        r.read_exact(&mut width)?;
+        ensure!(matches!(width[0], 4 | 8), "bad compressed flags (width {})", width[0]);
+        Self::read_body(r, nbits, width[0] as usize)
+    }
 
+    /// Pre-FMX4 layout: no width byte, always u32 values.
+    pub fn load_u32(r: &mut impl std::io::Read) -> Result<Self> {
+        let mut b8 = [0u8; 8];
         r.read_exact(&mut b8)?;
-        let ns = u64::from_le_bytes(b8) as usize;
-        let mut super_counts = vec![0u32; ns];
-        for i in 0..ns {
-            r.read_exact(&mut b4)?;
-            super_counts[i] = u32::from_le_bytes(b4);
-        }
+        Self::read_body(r, u64::from_le_bytes(b8), 4)
+    }
 
-        r.read_exact(&mut b8)?;
-        let no = u64::from_le_bytes(b8) as usize;
-        let mut ones = vec![0u32; no];
-        for i in 0..no {
-            r.read_exact(&mut b4)?;
-            ones[i] = u32::from_le_bytes(b4);
+    fn read_body(r: &mut impl std::io::Read, nbits: u64, width: usize) -> Result<Self> {
+        let nbits = usize::try_from(nbits).context("compressed flags longer than this platform's address space")?;
+        let mut b8 = [0u8; 8];
+        let mut read_vec = |r: &mut dyn std::io::Read| -> Result<Vec<u64>> {
+            r.read_exact(&mut b8)?;
+            let len = u64::from_le_bytes(b8) as usize;
+            let mut v = vec![0u64; len];
+            for x in v.iter_mut() {
+                let mut b = [0u8; 8];
+                r.read_exact(&mut b[..width])?;
+                *x = u64::from_le_bytes(b);
+            }
+            Ok(v)
+        };
+        let super_counts = read_vec(r)?;
+        let ones = read_vec(r)?;
+        ensure!(super_counts.len() == nbits.div_ceil(512) + 1, "bad compressed flags (superblocks)");
+        Ok(Self { nbits, super_every: 512, super_counts, ones })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rank_matches_naive() {
+        let ones = vec![0, 4, 511, 512, 1000, 1030];
+        let f = CompressedFlags::build(1100, &ones);
+        for pos in 0..=1100 {
+            let naive = ones.iter().filter(|&&p| p < pos).count() as u64;
+            assert_eq!(f.rank1(pos), naive, "pos={pos}");
         }
+    }
 
-        // rebuild super_offsets as a copy of super_counts[]
-        let super_offsets = super_counts.clone();
+    #[test]
+    fn sparse_past_4gib() {
+        // 4 GiB + 4 KiB bits, with ones on both sides of the u32 boundary
+        let b32 = 1usize << 32;
+        let nbits = b32 + 4096;
+        let ones = vec![7, b32 - 1, b32, b32 + 1, b32 + 513, nbits - 1];
+        let f = CompressedFlags::build(nbits, &ones);
+        let check = |f: &CompressedFlags| {
+            for pos in [0, 8, b32 - 1, b32, b32 + 1, b32 + 2, b32 + 513, b32 + 514, nbits - 1, nbits] {
+                assert_eq!(f.rank1(pos), ones.iter().filter(|&&p| p < pos).count() as u64, "pos={pos}");
+            }
+        };
+        check(&f);
 
-        Ok(Self {
-            nbits,
-            super_every: 512,
-            super_counts,
-            ones,
-            super_offsets,
-        })
+        let mut buf = Vec::new();
+        f.save(&mut buf).unwrap();
+        assert_eq!(buf[8], 8, "positions past u32 need the wide layout");
+        check(&CompressedFlags::load(&mut buf.as_slice()).unwrap());
     }
 }
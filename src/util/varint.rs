@@ -9,8 +9,12 @@ pub fn decode_varint(bytes: &[u8], mut i: usize) -> Result<(u64, usize)> {
         anyhow::ensure!(shift<=63,"varint too long");
     }
 }
+/// Signed delta as an unsigned varint payload: 0, -1, 1, -2, ... map to 0, 1, 2, 3, ...
+pub fn zigzag_encode(d: i64) -> u64 { ((d << 1) ^ (d >> 63)) as u64 }
+pub fn zigzag_decode(z: u64) -> i64 { ((z >> 1) as i64) ^ -((z & 1) as i64) }
 pub fn delta_encode(mut xs: Vec<u64>) -> Vec<u64> { if xs.is_empty(){return xs;} xs.sort_unstable(); let mut prev=0; for x in xs.iter_mut(){ let o=*x; *x-=prev; prev=o; } xs }
 pub fn delta_decode(mut xs: Vec<u64>) -> Vec<u64> { let mut acc=0; for x in xs.iter_mut(){ acc+=*x; *x=acc; } xs }
-pub fn encode_u64s_varint_delta(mut xs: Vec<u64>) -> Vec<u8> { let deltas=delta_encode(xs.drain(..).collect()); let mut out=Vec::with_capacity(deltas.len()*2); for d in deltas{ encode_varint(d,&mut out);} out }
+pub fn encode_u64s_varint_delta(mut xs: Vec<u64>) -> Vec<u8> { let deltas=delta_encode(std::mem::take(&mut xs)); let mut out=Vec::with_capacity(deltas.len()*2); for d in deltas{ encode_varint(d,&mut out);} out }
 pub fn decode_u64s_varint_delta(bytes: &[u8]) -> Result<Vec<u64>> { let mut i=0; let mut deltas=Vec::new(); while i<bytes.len(){ let (v,j)=decode_varint(bytes,i)?; i=j; deltas.push(v);} Ok(delta_decode(deltas)) }
-#[cfg(test)] mod tests { use super::*; #[test] fn roundtrip(){ let xs=vec![1,2,3,10,100,1000,10000,100000]; let buf=encode_u64s_varint_delta(xs.clone()); let ys=decode_u64s_varint_delta(&buf).unwrap(); assert_eq!(xs,ys);} }
+#[cfg(test)] mod tests { use super::*; #[test] fn roundtrip(){ let xs=vec![1,2,3,10,100,1000,10000,100000]; let buf=encode_u64s_varint_delta(xs.clone()); let ys=decode_u64s_varint_delta(&buf).unwrap(); assert_eq!(xs,ys);}
+    #[test] fn zigzag(){ for d in [0i64,-1,1,-2,63,-64,i64::MAX,i64::MIN]{ assert_eq!(zigzag_decode(zigzag_encode(d)),d); } let mut buf=Vec::new(); encode_varint(zigzag_encode(-5),&mut buf); assert_eq!(buf.len(),1); } }
@@ -0,0 +1,94 @@
+use crate::util::bitvec::RankBitVec;
+
+const LEVELS: usize = 8;
+
+/// Wavelet matrix over bytes: one rank bitvector per bit of the symbol, most
+/// significant first. Each level stably moves the symbols with a 0 bit ahead
+/// of those with a 1 bit, so the sequence costs about 8 bits per symbol (plus
+/// rank overhead) instead of one bitvector per symbol value.
+pub struct WaveletMatrix {
+    len: usize,
+    levels: Vec<RankBitVec>,
+    zeros: [u64; LEVELS], // number of 0 bits on each level
+}
+
+impl WaveletMatrix {
+    pub fn from_bytes(seq: &[u8]) -> Self {
+        let mut cur = seq.to_vec();
+        let mut next = Vec::with_capacity(seq.len());
+        let mut levels = Vec::with_capacity(LEVELS);
+        let mut zeros = [0u64; LEVELS];
+        for (l, z) in zeros.iter_mut().enumerate() {
+            let shift = LEVELS - 1 - l;
+            let mut words = vec![0u64; seq.len().div_ceil(64)];
+            for (i, &v) in cur.iter().enumerate() {
+                if (v >> shift) & 1 == 1 { words[i >> 6] |= 1u64 << (i & 63); }
+            }
+            next.clear();
+            next.extend(cur.iter().filter(|&&v| (v >> shift) & 1 == 0));
+            *z = next.len() as u64;
+            next.extend(cur.iter().filter(|&&v| (v >> shift) & 1 == 1));
+            std::mem::swap(&mut cur, &mut next);
+            levels.push(RankBitVec::from_bits(words));
+        }
+        Self { len: seq.len(), levels, zeros }
+    }
+
+    pub fn len(&self) -> usize { self.len }
+    pub fn is_empty(&self) -> bool { self.len == 0 }
+
+    /// Symbol at position `i`.
+    pub fn access(&self, i: usize) -> u8 { self.access_rank(i).0 }
+
+    /// Occurrences of `ch` in positions `0..i`.
+    pub fn rank(&self, ch: u8, i: usize) -> u64 {
+        let (mut p, mut i) = (0u64, i as u64);
+        for (l, bv) in self.levels.iter().enumerate() {
+            if (ch >> (LEVELS - 1 - l)) & 1 == 0 {
+                p -= bv.rank1(p as usize);
+                i -= bv.rank1(i as usize);
+            } else {
+                p = self.zeros[l] + bv.rank1(p as usize);
+                i = self.zeros[l] + bv.rank1(i as usize);
+            }
+        }
+        i - p
+    }
+
+    /// Symbol at `i` together with its occurrences before `i`, in one walk
+    /// (the two lookups an LF step needs).
+    pub fn access_rank(&self, i: usize) -> (u8, u64) {
+        let (mut ch, mut p, mut i) = (0u8, 0u64, i as u64);
+        for (l, bv) in self.levels.iter().enumerate() {
+            if !bv.get(i as usize) {
+                p -= bv.rank1(p as usize);
+                i -= bv.rank1(i as usize);
+            } else {
+                ch |= 1 << (LEVELS - 1 - l);
+                p = self.zeros[l] + bv.rank1(p as usize);
+                i = self.zeros[l] + bv.rank1(i as usize);
+            }
+        }
+        (ch, i - p)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rank_and_access_match_naive() {
+        let seq: Vec<u8> = (0..300u32).map(|i| (i * 37 % 11) as u8 * 23 + (i % 3) as u8).collect();
+        let wm = WaveletMatrix::from_bytes(&seq);
+        for i in 0..=seq.len() {
+            if i < seq.len() {
+                let naive = seq[..i].iter().filter(|&&c| c == seq[i]).count() as u64;
+                assert_eq!(wm.access_rank(i), (seq[i], naive));
+            }
+            for ch in [0u8, 1, 23, 47, 255] {
+                assert_eq!(wm.rank(ch, i), seq[..i].iter().filter(|&&c| c == ch).count() as u64);
+            }
+        }
+    }
+}